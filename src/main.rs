@@ -1,26 +1,39 @@
+use std::fs;
+use std::sync::Arc;
+
 use rustyline::error::ReadlineError;
 use rustyline::{Editor, Result};
 
 mod vm;
 
+// Typing this at the prompt before some forms prints what they expand to
+// instead of evaluating them.
+const EXPAND_COMMAND: &str = ":expand";
+
 fn main() -> Result<()> {
     let mut rl = Editor::<()>::new()?;
     if rl.load_history("history.txt").is_err() {
         println!("No previous history.");
     }
     let scope = vm::Scope::builtin();
+    for path in std::env::args().skip(1) {
+        load(&scope, &path);
+    }
     loop {
         let readline = rl.readline(">> ");
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
-                match vm::parse(&line).and_then(|value| vm::eval(&scope, &value)) {
+                if let Some(forms) = line.trim_start().strip_prefix(EXPAND_COMMAND) {
+                    print_expansions(&scope, forms);
+                    continue;
+                }
+                match vm::parse_program(&line).and_then(|values| vm::eval_program(&scope, &values))
+                {
                     Ok(value) => {
                         println!("{}", vm::to_string(&value));
                     }
-                    Err(err) => {
-                        println!("Error: {:?}", err);
-                    }
+                    Err(err) => println!("{}", err),
                 }
             }
             Err(ReadlineError::Interrupted) => {
@@ -40,6 +53,41 @@ fn main() -> Result<()> {
     rl.save_history("history.txt")
 }
 
+// Evaluates the forms in a file in order, stopping at the first error and
+// reporting where the form that failed starts.
+fn load(scope: &Arc<vm::Scope>, path: &str) {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => {
+            println!("Error: cannot read {}: {}", path, err);
+            return;
+        }
+    };
+    let (values, source_map) = match vm::parse_program_with_source_map(path, &content) {
+        Ok(parsed) => parsed,
+        Err(err) => return println!("{}", err),
+    };
+    for value in &values {
+        if let Err(err) = vm::eval(scope, &vm::prepare(scope, value)) {
+            if let Some(span) = source_map.span(value) {
+                print!("In {}: ", span);
+            }
+            return println!("{}", err);
+        }
+    }
+}
+
+fn print_expansions(scope: &Arc<vm::Scope>, forms: &str) {
+    match vm::parse_program(forms) {
+        Ok(values) => {
+            for value in &values {
+                println!("{}", vm::to_string(&vm::expand(scope, value)));
+            }
+        }
+        Err(err) => println!("{}", err),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::fs;
@@ -61,6 +109,7 @@ mod test {
             let mut tests = Vec::new();
             let mut lines = content.lines();
 
+            #[allow(clippy::while_let_on_iterator)]
             fn collect_until(lines: &mut Lines, marker: &str) -> String {
                 let mut result = Vec::new();
                 while let Some(line) = lines.next() {
@@ -90,6 +139,7 @@ mod test {
     }
 
     impl TestSuite {
+        #[allow(clippy::double_ended_iterator_last)]
         fn read(path: &str) -> TestSuite {
             let content = fs::read_to_string(path).unwrap();
             let tests = TestCase::parse(&content);
@@ -119,11 +169,12 @@ mod test {
             println!("{}: {} test cases", suite.name, suite.tests.len());
             for test in suite.tests {
                 let scope = vm::Scope::builtin();
-                let result = vm::parse(&test.input).and_then(|value| vm::eval(&scope, &value));
+                let result = vm::parse_program(&test.input)
+                    .and_then(|values| vm::eval_program(&scope, &values));
                 let output = match result {
                     Ok(value) => vm::to_string(&value),
                     Err(err) => {
-                        format!("#error: {:?}", err)
+                        format!("#error: {}", err)
                     }
                 };
                 assert_eq!(
@@ -0,0 +1,53 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::vm::values::*;
+use crate::vm::{Arity, Error, Symbol};
+
+fn as_condition(value: &Arc<Value>) -> Result<&Condition, Error> {
+    match value.deref() {
+        Value::Condition(condition) => Ok(condition),
+        _ => Err(Error::type_error("an error object", value)),
+    }
+}
+
+// (raise value) raises any value, which a guard clause then receives as is.
+pub fn raise(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Err(Error::raise(args[0].clone()))
+}
+
+// (error message irritant...) raises a condition object of kind error.
+pub fn error(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Arity::AtLeast(1).check(args.len())?;
+    let message = match args[0].deref() {
+        Value::String(message) => message.clone(),
+        _ => return Err(Error::type_error("a string", &args[0])),
+    };
+    Err(Error::raise(Condition::new(
+        USER_ERROR,
+        message,
+        args[1..].to_vec(),
+    )))
+}
+
+pub fn is_error_object(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Ok(Value::from_bool(matches!(
+        args[0].deref(),
+        Value::Condition(_)
+    )))
+}
+
+pub fn error_object_kind(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    let condition = as_condition(&args[0])?;
+    Ok(Arc::new(Value::Symbol(Symbol::intern(condition.kind))))
+}
+
+pub fn error_object_message(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    let condition = as_condition(&args[0])?;
+    Ok(Arc::new(Value::String(condition.message.clone())))
+}
+
+pub fn error_object_irritants(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    let condition = as_condition(&args[0])?;
+    Ok(Cell::from_vec(condition.irritants.clone()))
+}
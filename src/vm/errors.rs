@@ -0,0 +1,237 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::vm::parser::ParseError;
+use crate::vm::values::*;
+use crate::vm::Symbol;
+
+// The number of arguments an operator accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    Between(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(self, count: usize) -> bool {
+        match self {
+            Arity::Exactly(arity) => count == arity,
+            Arity::AtLeast(min) => count >= min,
+            Arity::Between(min, max) => (min..=max).contains(&count),
+        }
+    }
+
+    pub fn check(self, count: usize) -> Result<(), Error> {
+        if self.accepts(count) {
+            Ok(())
+        } else {
+            Err(Error::arity_error(self, count))
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+        match *self {
+            Arity::Exactly(arity) => write!(f, "{} argument{}", arity, plural(arity)),
+            Arity::AtLeast(min) => write!(f, "at least {} argument{}", min, plural(min)),
+            Arity::Between(min, max) => write!(f, "{} to {} arguments", min, max),
+        }
+    }
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug)]
+pub enum ErrorKind {
+    ParseError(ParseError),
+    EvalError(String),
+    TypeError {
+        expected: &'static str,
+        value: Arc<Value>,
+    },
+    ArityError {
+        expected: Arity,
+        actual: usize,
+    },
+    NotFoundError(Symbol),
+    // A letrec variable read before its init was evaluated.
+    UnassignedError(Symbol),
+    // A value raised from Lisp code by raise or error.
+    Raised(Arc<Value>),
+    // A continuation being called, on its way back to its call/cc. This is not
+    // an error as such and guard lets it through.
+    Escape {
+        id: usize,
+        value: Arc<Value>,
+    },
+}
+
+// An error along with where evaluation was when it happened. The operator is
+// the innermost function or special form whose call was active, and the
+// backtrace lists the lambdas that were being evaluated, innermost first.
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub operator: Option<String>,
+    pub backtrace: Vec<String>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Error {
+        Error {
+            kind,
+            operator: None,
+            backtrace: Vec::new(),
+        }
+    }
+
+    pub fn eval_error(message: String) -> Error {
+        Error::new(ErrorKind::EvalError(message))
+    }
+
+    pub fn type_error(expected: &'static str, value: &Arc<Value>) -> Error {
+        Error::new(ErrorKind::TypeError {
+            expected,
+            value: value.clone(),
+        })
+    }
+
+    pub fn arity_error(expected: Arity, actual: usize) -> Error {
+        Error::new(ErrorKind::ArityError { expected, actual })
+    }
+
+    pub fn not_found(name: Symbol) -> Error {
+        Error::new(ErrorKind::NotFoundError(name))
+    }
+
+    pub fn unassigned(name: Symbol) -> Error {
+        Error::new(ErrorKind::UnassignedError(name))
+    }
+
+    pub fn raise(value: Arc<Value>) -> Error {
+        Error::new(ErrorKind::Raised(value))
+    }
+
+    // Names a variable by how it was written in an error about it, when it
+    // was looked up under another name.
+    pub fn with_variable(mut self, original: &Symbol) -> Error {
+        if let ErrorKind::NotFoundError(name) | ErrorKind::UnassignedError(name) = &mut self.kind {
+            *name = original.clone();
+        }
+        self
+    }
+
+    pub fn is_escape(&self) -> bool {
+        matches!(self.kind, ErrorKind::Escape { .. })
+    }
+
+    // Records the operator being called, unless a call nested inside it
+    // already claimed the error.
+    pub fn with_operator(mut self, name: &str) -> Error {
+        if self.operator.is_none() {
+            self.operator = Some(name.to_string());
+        }
+        self
+    }
+
+    pub fn with_frame(mut self, name: &str) -> Error {
+        self.backtrace.push(name.to_string());
+        self
+    }
+
+    fn kind_name(&self) -> &'static str {
+        match &self.kind {
+            ErrorKind::ParseError(_) => "parse-error",
+            ErrorKind::EvalError(_) => "eval-error",
+            ErrorKind::TypeError { .. } => "type-error",
+            ErrorKind::ArityError { .. } => "arity-error",
+            ErrorKind::NotFoundError(_) => "unbound-symbol",
+            ErrorKind::UnassignedError(_) => "unassigned-variable",
+            ErrorKind::Raised(_) => USER_ERROR,
+            ErrorKind::Escape { .. } => "escape",
+        }
+    }
+
+    // The value a guard clause sees: whatever was raised, or a condition
+    // object describing a built-in error.
+    pub fn to_condition(&self) -> Arc<Value> {
+        let irritants = match &self.kind {
+            ErrorKind::Raised(value) => return value.clone(),
+            ErrorKind::TypeError { value, .. } => vec![value.clone()],
+            ErrorKind::NotFoundError(name) | ErrorKind::UnassignedError(name) => {
+                vec![Arc::new(Value::Symbol(name.clone()))]
+            }
+            _ => Vec::new(),
+        };
+        Condition::new(self.kind_name(), self.headline(), irritants)
+    }
+
+    // The first line of the printed error, without the backtrace.
+    pub fn headline(&self) -> String {
+        let title = match &self.kind {
+            ErrorKind::ParseError(err) => {
+                return format!("Parse error at {}: {}", err.span, err.message)
+            }
+            ErrorKind::Raised(value) => {
+                return match value.as_ref() {
+                    Value::Condition(condition) => condition.describe(),
+                    _ => format!("Uncaught exception: {}", to_string(value)),
+                }
+            }
+            ErrorKind::Escape { .. } => {
+                return "Continuation called outside of its call/cc".to_string()
+            }
+            ErrorKind::EvalError(_) => "Error",
+            ErrorKind::TypeError { .. } => "Type error",
+            ErrorKind::ArityError { .. } => "Arity error",
+            ErrorKind::NotFoundError(_) => "Unbound symbol",
+            ErrorKind::UnassignedError(_) => "Unassigned variable",
+        };
+        let mut buffer = title.to_string();
+        if let Some(operator) = &self.operator {
+            buffer.push_str(" in ");
+            buffer.push_str(operator);
+        }
+        let detail = match &self.kind {
+            ErrorKind::ParseError(_) | ErrorKind::Raised(_) | ErrorKind::Escape { .. } => {
+                unreachable!()
+            }
+            ErrorKind::EvalError(message) => message.clone(),
+            ErrorKind::TypeError { expected, value } => {
+                format!("expected {}, got {}", expected, to_string(value))
+            }
+            ErrorKind::ArityError { expected, actual } => {
+                format!("expected {}, got {}", expected, actual)
+            }
+            ErrorKind::NotFoundError(name) | ErrorKind::UnassignedError(name) => name.to_string(),
+        };
+        buffer.push_str(": ");
+        buffer.push_str(&detail);
+        buffer
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        Error::new(ErrorKind::ParseError(err))
+    }
+}
+
+// Only the innermost frames are printed, since a runaway recursion can leave
+// thousands of them.
+const MAX_BACKTRACE: usize = 10;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.headline())?;
+        for name in self.backtrace.iter().take(MAX_BACKTRACE) {
+            write!(f, "\n  in {}", name)?;
+        }
+        if self.backtrace.len() > MAX_BACKTRACE {
+            write!(f, "\n  ... {} more", self.backtrace.len() - MAX_BACKTRACE)?;
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,15 @@
 use std::collections::HashMap;
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock};
 
+use crate::vm::conditions;
+use crate::vm::expand;
+use crate::vm::numbers;
+use crate::vm::predicates;
+use crate::vm::strings;
+use crate::vm::symbols;
+use crate::vm::syntax_rules;
 use crate::vm::values::*;
 use crate::vm::*;
 
@@ -11,15 +19,35 @@ fn cons(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
 
 fn car(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
     match args[0].deref() {
-        Value::Cell(cell) => Ok(cell.left.clone()),
-        _ => Err(Error::TypeError),
+        Value::Cell(cell) => Ok(cell.left()),
+        _ => Err(Error::type_error("a pair", &args[0])),
     }
 }
 
 fn cdr(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
     match args[0].deref() {
-        Value::Cell(cell) => Ok(cell.right.clone()),
-        _ => Err(Error::TypeError),
+        Value::Cell(cell) => Ok(cell.right()),
+        _ => Err(Error::type_error("a pair", &args[0])),
+    }
+}
+
+fn set_car(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    match args[0].deref() {
+        Value::Cell(cell) => {
+            cell.set_left(args[1].clone());
+            Ok(args[1].clone())
+        }
+        _ => Err(Error::type_error("a pair", &args[0])),
+    }
+}
+
+fn set_cdr(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    match args[0].deref() {
+        Value::Cell(cell) => {
+            cell.set_right(args[1].clone());
+            Ok(args[1].clone())
+        }
+        _ => Err(Error::type_error("a pair", &args[0])),
     }
 }
 
@@ -27,149 +55,880 @@ fn list(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
     Ok(Cell::from_vec(args.to_vec()))
 }
 
-fn quote(_scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
-    Ok(args[0].clone())
+fn quote(_scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    Ok(Trampoline::Return(args[0].clone()))
 }
 
-fn lambda(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
-    let formals: Vec<String> = args[0]
-        .to_args()?
-        .iter()
-        .map(|value| -> Result<String, Error> {
-            match value.as_ref() {
-                Value::Symbol(symbol) => Ok(symbol.clone()),
-                _ => Err(Error::TypeError),
+// Bodies of lambda, macro and the let family hold one or more expressions,
+// evaluated in order as if wrapped in begin. The body is whatever follows the
+// first `start` arguments of the form.
+fn parse_body(args: &[Arc<Value>], start: usize) -> Result<Vec<Arc<Value>>, Error> {
+    Arity::AtLeast(start + 1).check(args.len())?;
+    Ok(args[start..].to_vec())
+}
+
+fn lambda(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let body = parse_body(args, 1)?;
+    let formals = Formals::parse(&args[0])?;
+    Ok(Trampoline::Return(Lambda::new(
+        "#lambda".to_string(),
+        scope.clone(),
+        formals,
+        body,
+    )))
+}
+
+fn macro_fn(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let body = parse_body(args, 1)?;
+    let formals = Formals::parse(&args[0])?;
+    Ok(Trampoline::Return(Macro::new(scope.clone(), formals, body)))
+}
+
+// (define name value) binds name in the current scope, and
+// (define (name formals...) body...) is shorthand for binding a lambda.
+fn define(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    Arity::AtLeast(1).check(args.len())?;
+    if let Some(name) = args[0].identifier() {
+        Arity::Exactly(2).check(args.len())?;
+        let value = eval(scope, &args[1])?;
+        scope.bind(name, value);
+        return Ok(Trampoline::Return(args[0].clone()));
+    }
+    match args[0].deref() {
+        Value::Cell(cell) => {
+            let signature = cell.left();
+            let name = signature
+                .identifier()
+                .ok_or_else(|| Error::type_error("a symbol", &signature))?;
+            let formals = Formals::parse(&cell.right())?;
+            let body = parse_body(args, 1)?;
+            scope.bind(
+                name,
+                Lambda::new(to_string(&signature), scope.clone(), formals, body),
+            );
+            Ok(Trampoline::Return(signature.clone()))
+        }
+        _ => Err(Error::type_error("a symbol or a list", &args[0])),
+    }
+}
+
+fn begin(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    eval_body(scope, args)
+}
+
+// (if test then [else]), where a missing else branch gives nil.
+fn if_fn(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    Arity::Between(2, 3).check(args.len())?;
+    let condition = eval(scope, &args[0])?;
+    if condition.is_truthy() {
+        let then_clause = &args[1];
+        Ok(Trampoline::TailCall(scope.clone(), then_clause.clone()))
+    } else if let Some(else_clause) = args.get(2) {
+        Ok(Trampoline::TailCall(scope.clone(), else_clause.clone()))
+    } else {
+        Ok(Trampoline::Return(Value::nil()))
+    }
+}
+
+// (when test body...) evaluates body if test is true, giving nil otherwise.
+fn when(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let body = parse_body(args, 1)?;
+    if eval(scope, &args[0])?.is_truthy() {
+        eval_body(scope, &body)
+    } else {
+        Ok(Trampoline::Return(Value::nil()))
+    }
+}
+
+// (unless test body...) evaluates body if test is false, giving nil otherwise.
+fn unless(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let body = parse_body(args, 1)?;
+    if eval(scope, &args[0])?.is_truthy() {
+        Ok(Trampoline::Return(Value::nil()))
+    } else {
+        eval_body(scope, &body)
+    }
+}
+
+// (and expr...) gives the first false value, or the value of the last
+// expression, which is evaluated as a tail call. With no expressions it is #t.
+fn and(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let Some((last, init)) = args.split_last() else {
+        return Ok(Trampoline::Return(Value::from_bool(true)));
+    };
+    for arg in init {
+        let value = eval(scope, arg)?;
+        if !value.is_truthy() {
+            return Ok(Trampoline::Return(value));
+        }
+    }
+    Ok(Trampoline::TailCall(scope.clone(), last.clone()))
+}
+
+// (or expr...) gives the first true value, or the value of the last
+// expression, which is evaluated as a tail call. With no expressions it is #f.
+fn or(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let Some((last, init)) = args.split_last() else {
+        return Ok(Trampoline::Return(Value::from_bool(false)));
+    };
+    for arg in init {
+        let value = eval(scope, arg)?;
+        if value.is_truthy() {
+            return Ok(Trampoline::Return(value));
+        }
+    }
+    Ok(Trampoline::TailCall(scope.clone(), last.clone()))
+}
+
+// (set! name value) replaces the nearest existing binding of name.
+fn set(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let value = match args[0].deref() {
+        Value::Symbol(name) => {
+            let value = eval(scope, &args[1])?;
+            scope.set(name, value.clone())?;
+            value
+        }
+        Value::Alias(alias) => {
+            let value = eval(scope, &args[1])?;
+            alias.set(scope, value.clone())?;
+            value
+        }
+        Value::Local(local) => {
+            let value = eval(scope, &args[1])?;
+            scope.set_local(local, value.clone())?;
+            value
+        }
+        _ => return Err(Error::type_error("a symbol", &args[0])),
+    };
+    Ok(Trampoline::Return(value))
+}
+
+// Picks the first clause whose test is true, as cond does. A clause is
+// (test expr...), where an else test is always true, an empty body gives the
+// value of the test, and (test => receiver) calls receiver with that value.
+// Returns None when no clause matches.
+fn cond_clauses(scope: &Arc<Scope>, clauses: &[Arc<Value>]) -> Result<Option<Trampoline>, Error> {
+    for clause in clauses {
+        let parts = clause.to_args()?;
+        let (test, body) = parts
+            .split_first()
+            .ok_or_else(|| Error::type_error("a (test expr...) clause", clause))?;
+        let value = if test.is_keyword(&symbols::ELSE) {
+            Value::from_bool(true)
+        } else {
+            eval(scope, test)?
+        };
+        if value.is_truthy() {
+            return clause_body(scope, value, body).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+// Evaluates the body of a cond or case clause that was picked because of
+// value. A body of the form (=> receiver) calls receiver with the value.
+fn clause_body(
+    scope: &Arc<Scope>,
+    value: Arc<Value>,
+    body: &[Arc<Value>],
+) -> Result<Trampoline, Error> {
+    match body {
+        [] => Ok(Trampoline::Return(value)),
+        [arrow, receiver] if arrow.is_keyword(&symbols::ARROW) => {
+            let receiver = eval(scope, receiver)?;
+            apply(&receiver, &[value])
+        }
+        _ => eval_body(scope, body),
+    }
+}
+
+// (cond clause...) picks the first clause whose test is true, giving nil when
+// there is none.
+fn cond(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    Ok(cond_clauses(scope, args)?.unwrap_or(Trampoline::Return(Value::nil())))
+}
+
+// (case key ((datum...) expr...) ... [(else expr...)]) picks the first clause
+// listing a datum eqv? to the value of key. The data are not evaluated.
+fn case(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    Arity::AtLeast(1).check(args.len())?;
+    let key = eval(scope, &args[0])?;
+    for clause in &args[1..] {
+        let parts = clause.to_args()?;
+        let (data, body) = parts
+            .split_first()
+            .ok_or_else(|| Error::type_error("a ((datum...) expr...) clause", clause))?;
+        if data.is_keyword(&symbols::ELSE)
+            || data
+                .to_args()?
+                .iter()
+                .any(|datum| predicates::is_eqv(datum, &key))
+        {
+            if body.is_empty() {
+                return Err(Error::type_error("a ((datum...) expr...) clause", clause));
             }
-        })
-        .collect::<Result<Vec<_>, Error>>()?;
-    let body = args[1].clone();
-    Ok(Lambda::new(scope.clone(), formals, body))
+            return clause_body(scope, key, body);
+        }
+    }
+    Ok(Trampoline::Return(Value::nil()))
+}
+
+// (guard (var clause...) body...) evaluates body, and if it raises, binds
+// what was raised to var and picks a clause as cond does. Built-in errors
+// are bound as condition objects. If no clause matches, the error carries on.
+fn guard(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let body = parse_body(args, 1)?;
+    let spec = args[0].to_args()?;
+    let (name, clauses) = match spec.split_first() {
+        Some((name, clauses)) => match name.identifier() {
+            Some(name) => (name, clauses),
+            None => return Err(Error::type_error("a symbol", name)),
+        },
+        None => return Err(Error::type_error("a (var clause...) list", &args[0])),
+    };
+    // The body is not in tail position, since the guard has to stay around
+    // to catch what it raises.
+    let err = match eval_body(scope, &body).and_then(Trampoline::resolve) {
+        Ok(value) => return Ok(Trampoline::Return(value)),
+        Err(err) if err.is_escape() => return Err(err),
+        Err(err) => err,
+    };
+    let handler_scope = scope.new_child(vec![(name, err.to_condition())]);
+    match cond_clauses(&handler_scope, clauses)? {
+        Some(next) => Ok(next),
+        None => Err(err),
+    }
+}
+
+// (call/cc receiver) calls receiver with the continuation of the call/cc,
+// which escapes back out of it. Since the continuation works by unwinding,
+// the call to receiver is not in tail position.
+fn call_cc(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    let (continuation, id, active) = Continuation::new();
+    let result = apply(&args[0], &[continuation]).and_then(Trampoline::resolve);
+    active.store(false, Ordering::Relaxed);
+    match result {
+        Err(Error {
+            kind: ErrorKind::Escape { id: target, value },
+            ..
+        }) if target == id => Ok(value),
+        result => result,
+    }
+}
+
+fn call_thunk(thunk: &Arc<Value>) -> Result<Arc<Value>, Error> {
+    apply(thunk, &[])?.resolve()
+}
+
+// (dynamic-wind before thunk after) calls the three thunks in order, calling
+// after however thunk exits: by returning, by an error, or by a continuation
+// escaping through it. Continuations cannot re-enter, so before runs once.
+fn dynamic_wind(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    call_thunk(&args[0])?;
+    let result = call_thunk(&args[1]);
+    call_thunk(&args[2])?;
+    result
 }
 
-fn macro_fn(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
-    let formals: Vec<String> = args[0]
+// (unwind-protect protected cleanup...) evaluates protected, then the cleanup
+// expressions however protected exits, giving the value of protected.
+fn unwind_protect(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    Arity::AtLeast(1).check(args.len())?;
+    let result = eval(scope, &args[0]);
+    eval_body(scope, &args[1..])?.resolve()?;
+    result.map(Trampoline::Return)
+}
+
+// Parses the ((name init) ...) list at the head of the let family of forms.
+fn parse_bindings(bindings: &Arc<Value>) -> Result<Vec<(Symbol, Arc<Value>)>, Error> {
+    bindings
         .to_args()?
         .iter()
-        .map(|value| -> Result<String, Error> {
-            match value.as_ref() {
-                Value::Symbol(symbol) => Ok(symbol.clone()),
-                _ => Err(Error::TypeError),
+        .map(|binding| {
+            let pair = binding.to_args()?;
+            match (
+                pair.as_slice(),
+                pair.first().and_then(|name| name.identifier()),
+            ) {
+                ([_, init], Some(name)) => Ok((name, init.clone())),
+                _ => Err(Error::type_error("a (name init) binding", binding)),
             }
         })
+        .collect()
+}
+
+fn eval_inits(
+    scope: &Arc<Scope>,
+    bindings: Vec<(Symbol, Arc<Value>)>,
+) -> Result<Vec<(Symbol, Arc<Value>)>, Error> {
+    bindings
+        .into_iter()
+        .map(|(name, init)| Ok((name, eval(scope, &init)?)))
+        .collect()
+}
+
+// (let ((name init) ...) body...) evaluates every init in the enclosing scope.
+fn let_fn(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let body = parse_body(args, 1)?;
+    if let Some(name) = args[0].identifier() {
+        return named_let(scope, name, args);
+    }
+    let values = eval_inits(scope, parse_bindings(&args[0])?)?;
+    eval_body(&scope.new_child(values), &body)
+}
+
+// The named form (let loop ((name init) ...) body...) also binds loop to a
+// procedure taking the names as arguments, which is how Lisp writes loops.
+fn named_let(scope: &Arc<Scope>, name: Symbol, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let body = parse_body(args, 2)?;
+    let (formals, inits): (Vec<_>, Vec<_>) = parse_bindings(&args[1])?.into_iter().unzip();
+    let values = inits
+        .iter()
+        .map(|init| eval(scope, init))
         .collect::<Result<Vec<_>, Error>>()?;
-    let body = args[1].clone();
-    Ok(Macro::new(scope.clone(), formals, body))
+    let loop_scope = scope.new_child(Vec::new());
+    let procedure = Lambda::new(
+        name.to_string(),
+        loop_scope.clone(),
+        Formals::from_names(formals),
+        body,
+    );
+    loop_scope.bind(name, procedure.clone());
+    match procedure.deref() {
+        Value::Function(function) => function.tail_call(&values),
+        _ => unreachable!(),
+    }
 }
 
-fn if_fn(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
-    let condition = eval(scope, &args[0])?;
-    if condition.is_truthy() {
-        let then_clause = &args[1];
-        eval(scope, then_clause)
-    } else {
-        let else_clause = &args[2];
-        eval(scope, else_clause)
+// let* evaluates each init in a scope that already holds the bindings before it.
+fn let_star(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let body = parse_body(args, 1)?;
+    let mut scope = scope.clone();
+    for (name, init) in parse_bindings(&args[0])? {
+        let value = eval(&scope, &init)?;
+        scope = scope.new_child(vec![(name, value)]);
+    }
+    eval_body(&scope, &body)
+}
+
+// letrec evaluates every init in the new scope, so they can refer to each
+// other, and only binds their values once all of them are done. Reading a
+// name before that is an error.
+fn letrec(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let body = parse_body(args, 1)?;
+    let bindings = parse_bindings(&args[0])?;
+    let scope = scope.new_child(
+        bindings
+            .iter()
+            .map(|(name, _)| (name.clone(), Value::unassigned()))
+            .collect(),
+    );
+    for (name, value) in eval_inits(&scope, bindings)? {
+        scope.bind(name, value);
+    }
+    eval_body(&scope, &body)
+}
+
+// letrec* binds each value as soon as it is evaluated, so later inits can use
+// the values of earlier ones.
+fn letrec_star(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let body = parse_body(args, 1)?;
+    let bindings = parse_bindings(&args[0])?;
+    let scope = scope.new_child(
+        bindings
+            .iter()
+            .map(|(name, _)| (name.clone(), Value::unassigned()))
+            .collect(),
+    );
+    for (name, init) in bindings {
+        let value = eval(&scope, &init)?;
+        scope.bind(name, value);
     }
+    eval_body(&scope, &body)
 }
 
-#[derive(Debug, PartialEq, Eq)]
+// The value of the variable name, unless letrec has not assigned it yet.
+fn assigned(name: &Symbol, value: &Arc<Value>) -> Result<Arc<Value>, Error> {
+    match value.deref() {
+        Value::Unassigned => Err(Error::unassigned(name.clone())),
+        _ => Ok(value.clone()),
+    }
+}
+
+// The bindings of one scope. Local scopes hold a few bindings each, kept in
+// the order they were made so that a variable can be found by its position
+// as well as by name. The global scope holds every builtin, so it is a map.
+#[derive(Debug)]
+enum Frame {
+    Slots(Vec<(Symbol, Arc<Value>)>),
+    Map(HashMap<Symbol, Arc<Value>>),
+}
+
+impl Frame {
+    fn get(&self, name: &Symbol) -> Option<&Arc<Value>> {
+        match self {
+            Frame::Slots(slots) => slots
+                .iter()
+                .find(|(slot, _)| slot == name)
+                .map(|(_, value)| value),
+            Frame::Map(bindings) => bindings.get(name),
+        }
+    }
+
+    fn get_mut(&mut self, name: &Symbol) -> Option<&mut Arc<Value>> {
+        match self {
+            Frame::Slots(slots) => slots
+                .iter_mut()
+                .find(|(slot, _)| slot == name)
+                .map(|(_, value)| value),
+            Frame::Map(bindings) => bindings.get_mut(name),
+        }
+    }
+
+    // The slot at index, if it holds name.
+    fn slot(&self, name: &Symbol, index: usize) -> Option<&Arc<Value>> {
+        match self {
+            Frame::Slots(slots) => match slots.get(index) {
+                Some((slot, value)) if slot == name => Some(value),
+                _ => None,
+            },
+            Frame::Map(_) => None,
+        }
+    }
+
+    fn slot_mut(&mut self, name: &Symbol, index: usize) -> Option<&mut Arc<Value>> {
+        match self {
+            Frame::Slots(slots) => match slots.get_mut(index) {
+                Some((slot, value)) if slot == name => Some(value),
+                _ => None,
+            },
+            Frame::Map(_) => None,
+        }
+    }
+
+    fn insert(&mut self, name: Symbol, value: Arc<Value>) {
+        match self.get_mut(&name) {
+            Some(slot) => *slot = value,
+            None => match self {
+                Frame::Slots(slots) => slots.push((name, value)),
+                Frame::Map(bindings) => {
+                    bindings.insert(name, value);
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct Scope {
-    bindings: HashMap<String, Arc<Value>>,
+    // Bindings are behind a lock so that `define` can add to a scope that is
+    // already shared, such as the global scope of the REPL.
+    frame: RwLock<Frame>,
     parent: Option<Arc<Scope>>,
 }
 
+// Scopes are compared by identity; two distinct scopes are never equal even if
+// they happen to hold the same bindings right now.
+impl std::cmp::PartialEq for Scope {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
 impl Scope {
     pub fn builtin() -> Arc<Scope> {
-        let mut scope = Scope {
-            bindings: HashMap::new(),
+        let scope = Scope {
+            frame: RwLock::new(Frame::Map(HashMap::new())),
             parent: None,
         };
 
         scope.bind_native("cons", 2, cons);
         scope.bind_native("car", 1, car);
         scope.bind_native("cdr", 1, cdr);
+        scope.bind_native("set-car!", 2, set_car);
+        scope.bind_native("set-cdr!", 2, set_cdr);
         scope.bind_variadict_native("list", list);
+        scope.bind_native("eq?", 2, predicates::eq);
+        scope.bind_native("eqv?", 2, predicates::eqv);
+        scope.bind_native("equal?", 2, predicates::equal);
+        scope.bind_native("not", 1, predicates::not);
+        scope.bind_native("null?", 1, predicates::is_null);
+        scope.bind_native("pair?", 1, predicates::is_pair);
+        scope.bind_native("list?", 1, predicates::is_list);
+        scope.bind_native("symbol?", 1, predicates::is_symbol);
+        scope.bind_native("number?", 1, predicates::is_number);
+        scope.bind_native("string?", 1, predicates::is_string);
+        scope.bind_native("boolean?", 1, predicates::is_boolean);
+        scope.bind_native("procedure?", 1, predicates::is_procedure);
+        scope.bind_variadict_native("+", numbers::add);
+        scope.bind_variadict_native("-", numbers::sub);
+        scope.bind_variadict_native("*", numbers::mul);
+        scope.bind_variadict_native("/", numbers::div);
+        scope.bind_variadict_native("<", numbers::less);
+        scope.bind_variadict_native(">", numbers::greater);
+        scope.bind_variadict_native("=", numbers::equal);
+        scope.bind_variadict_native("<=", numbers::less_or_equal);
+        scope.bind_variadict_native(">=", numbers::greater_or_equal);
+        scope.bind_native("string-length", 1, strings::string_length);
+        scope.bind_variadict_native("substring", strings::substring);
+        scope.bind_variadict_native("string-append", strings::string_append);
+        scope.bind_native("string->symbol", 1, symbols::string_to_symbol);
+        scope.bind_native("symbol->string", 1, symbols::symbol_to_string);
+        scope.bind_variadict_native("gensym", symbols::gensym);
+        scope.bind_variadict_native("string-split", strings::string_split);
+        scope.bind_variadict_native("string-join", strings::string_join);
+        scope.bind_native("call/cc", 1, call_cc);
+        scope.bind_native("call-with-current-continuation", 1, call_cc);
+        scope.bind_native("dynamic-wind", 3, dynamic_wind);
+        scope.bind_native("raise", 1, conditions::raise);
+        scope.bind_variadict_native("error", conditions::error);
+        scope.bind_native("error-object?", 1, conditions::is_error_object);
+        scope.bind_native("error-object-kind", 1, conditions::error_object_kind);
+        scope.bind_native("error-object-message", 1, conditions::error_object_message);
+        scope.bind_native(
+            "error-object-irritants",
+            1,
+            conditions::error_object_irritants,
+        );
         scope.bind_special_form("quote", 1, quote);
-        scope.bind_special_form("lambda", 2, lambda);
-        scope.bind_special_form("if", 3, if_fn);
-        scope.bind_special_form("macro", 2, macro_fn);
+        scope.bind_variadic_special_form("lambda", lambda);
+        scope.bind_variadic_special_form("if", if_fn);
+        scope.bind_variadic_special_form("when", when);
+        scope.bind_variadic_special_form("unless", unless);
+        scope.bind_variadic_special_form("and", and);
+        scope.bind_variadic_special_form("or", or);
+        scope.bind_variadic_special_form("cond", cond);
+        scope.bind_variadic_special_form("case", case);
+        scope.bind_variadic_special_form("macro", macro_fn);
+        scope.bind_variadic_special_form("define", define);
+        scope.bind_variadic_special_form("begin", begin);
+        scope.bind_special_form("set!", 2, set);
+        scope.bind_variadic_special_form("let", let_fn);
+        scope.bind_variadic_special_form("let*", let_star);
+        scope.bind_variadic_special_form("letrec", letrec);
+        scope.bind_variadic_special_form("letrec*", letrec_star);
+        scope.bind_variadic_special_form("guard", guard);
+        scope.bind_variadic_special_form("unwind-protect", unwind_protect);
+        scope.bind_variadic_special_form("syntax-rules", syntax_rules::syntax_rules);
+        scope.bind_special_form("define-syntax", 2, syntax_rules::define_syntax);
+        scope.bind_special_form("macroexpand-1", 1, expand::macroexpand_1);
+        scope.bind_special_form("macroexpand", 1, expand::macroexpand);
 
         Arc::new(scope)
     }
 
-    pub fn bind_native(
-        &mut self,
-        name: &'static str,
-        arity: usize,
-        native: fn(&[Arc<Value>]) -> Result<Arc<Value>, Error>,
-    ) {
-        self.bind(name, Func::from_native_with_arity(name, native, arity));
+    pub fn bind_native(&self, name: &'static str, arity: usize, native: NativeFunction) {
+        self.bind(
+            Symbol::intern(name),
+            Func::from_native_with_arity(name, native, arity),
+        );
+    }
+
+    pub fn bind_variadict_native(&self, name: &'static str, native: NativeFunction) {
+        self.bind(Symbol::intern(name), Func::from_native(name, native));
     }
 
-    pub fn bind_variadict_native(
-        &mut self,
-        name: &'static str,
-        native: fn(&[Arc<Value>]) -> Result<Arc<Value>, Error>,
-    ) {
-        self.bind(name, Func::from_native(name, native));
+    pub fn bind_special_form(&self, name: &'static str, arity: usize, native: NativeSpecialForm) {
+        self.bind(
+            Symbol::intern(name),
+            SpecialForm::from_native(name, arity, native),
+        );
     }
 
-    pub fn bind_special_form(
-        &mut self,
-        name: &'static str,
-        arity: usize,
-        native: NativeSpecialForm,
-    ) {
-        self.bind(name, SpecialForm::from_native(name, arity, native));
+    pub fn bind_variadic_special_form(&self, name: &'static str, native: NativeSpecialForm) {
+        self.bind(
+            Symbol::intern(name),
+            SpecialForm::from_variadic_native(name, native),
+        );
     }
 
-    pub fn bind(&mut self, name: &str, value: Arc<Value>) {
-        self.bindings.insert(name.to_string(), value);
+    pub fn bind(&self, name: Symbol, value: Arc<Value>) {
+        self.frame.write().unwrap().insert(name, value);
     }
 
-    pub fn lookup(&self, name: &str) -> Result<Arc<Value>, Error> {
-        if let Some(value) = self.bindings.get(name) {
-            Ok(value.clone())
+    pub fn lookup(&self, name: &Symbol) -> Result<Arc<Value>, Error> {
+        if let Some(value) = self.frame.read().unwrap().get(name) {
+            assigned(name, value)
         } else {
             if let Some(parent) = self.parent.as_ref() {
                 parent.lookup(name)
             } else {
-                Err(Error::NotFoundError(name.to_string()))
+                Err(Error::not_found(name.clone()))
+            }
+        }
+    }
+
+    // Walks the scope chain like lookup does, replacing the first binding found.
+    pub fn set(&self, name: &Symbol, value: Arc<Value>) -> Result<(), Error> {
+        if let Some(slot) = self.frame.write().unwrap().get_mut(name) {
+            *slot = value;
+            return Ok(());
+        }
+        if let Some(parent) = self.parent.as_ref() {
+            parent.set(name, value)
+        } else {
+            Err(Error::not_found(name.clone()))
+        }
+    }
+
+    // The scope depth levels out from this one.
+    fn ancestor(&self, depth: usize) -> Option<&Scope> {
+        let mut scope = self;
+        for _ in 0..depth {
+            scope = scope.parent.as_deref()?;
+        }
+        Some(scope)
+    }
+
+    // Looks up a variable by the address resolve gave it. If the slot there
+    // holds some other variable, because the scope was built differently
+    // from what resolve could see, such as when a define in it did not run,
+    // the variable is looked up by name instead.
+    pub fn lookup_local(&self, local: &Local) -> Result<Arc<Value>, Error> {
+        if let Some(scope) = self.ancestor(local.depth) {
+            if let Some(value) = scope.frame.read().unwrap().slot(&local.symbol, local.index) {
+                return assigned(&local.original, value);
+            }
+        }
+        #[cfg(test)]
+        count_local_fallback();
+        self.lookup(&local.symbol)
+            .map_err(|err| err.with_variable(&local.original))
+    }
+
+    pub fn set_local(&self, local: &Local, value: Arc<Value>) -> Result<(), Error> {
+        if let Some(scope) = self.ancestor(local.depth) {
+            let mut frame = scope.frame.write().unwrap();
+            if let Some(slot) = frame.slot_mut(&local.symbol, local.index) {
+                *slot = value;
+                return Ok(());
             }
         }
+        #[cfg(test)]
+        count_local_fallback();
+        self.set(&local.symbol, value)
+            .map_err(|err| err.with_variable(&local.original))
     }
 
-    pub fn new_child(self: &Arc<Self>, bindings: HashMap<String, Arc<Value>>) -> Arc<Scope> {
+    // Bindings are added to the new scope in order, and a name that appears
+    // twice keeps its first slot.
+    pub fn new_child(self: &Arc<Self>, bindings: Vec<(Symbol, Arc<Value>)>) -> Arc<Scope> {
+        let mut frame = Frame::Slots(Vec::with_capacity(bindings.len()));
+        for (name, value) in bindings {
+            frame.insert(name, value);
+        }
         Arc::new(Scope {
-            bindings,
+            frame: RwLock::new(frame),
             parent: Some(self.clone()),
         })
     }
 }
 
+// Tests count how many times lookup_local and set_local fall back to a name.
+#[cfg(test)]
+thread_local! {
+    static LOCAL_FALLBACKS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+#[cfg(test)]
+fn count_local_fallback() {
+    LOCAL_FALLBACKS.set(LOCAL_FALLBACKS.get() + 1);
+}
+
+// How many local variables this thread has looked up or set by name because
+// their slot held some other variable.
+#[cfg(test)]
+pub fn local_fallbacks() -> usize {
+    LOCAL_FALLBACKS.get()
+}
+
+// Fills in a quasiquote template. The depth counts the quasiquotes enclosing
+// the template, so that only unquotes belonging to the outermost one are
+// evaluated and nested templates are rebuilt with their own unquotes intact.
+fn quasiquote(
+    scope: &Arc<Scope>,
+    template: &Arc<Value>,
+    depth: usize,
+) -> Result<Arc<Value>, Error> {
+    match template.deref() {
+        Value::Unquoted(value) if depth == 1 => eval(scope, value),
+        Value::Unquoted(value) => Ok(Arc::new(Value::Unquoted(quasiquote(
+            scope,
+            value,
+            depth - 1,
+        )?))),
+        Value::UnquoteSpliced(_) if depth == 1 => Err(Error::eval_error(format!(
+            "Unquote-splicing outside of a list: {}",
+            to_string(template)
+        ))),
+        Value::UnquoteSpliced(value) => Ok(Arc::new(Value::UnquoteSpliced(quasiquote(
+            scope,
+            value,
+            depth - 1,
+        )?))),
+        Value::Quasiquoted(value) => Ok(Arc::new(Value::Quasiquoted(quasiquote(
+            scope,
+            value,
+            depth + 1,
+        )?))),
+        Value::Quoted(value) => Ok(Arc::new(Value::Quoted(quasiquote(scope, value, depth)?))),
+        Value::Cell(_) => quasiquote_list(scope, template, depth),
+        _ => Ok(template.clone()),
+    }
+}
+
+fn quasiquote_list(
+    scope: &Arc<Scope>,
+    template: &Arc<Value>,
+    depth: usize,
+) -> Result<Arc<Value>, Error> {
+    let mut items = Vec::new();
+    let mut current = template.clone();
+    while let Value::Cell(cell) = current.deref() {
+        let item = cell.left();
+        match item.deref() {
+            Value::UnquoteSpliced(value) if depth == 1 => {
+                items.extend(eval(scope, value)?.to_args()?);
+            }
+            _ => items.push(quasiquote(scope, &item, depth)?),
+        }
+        current = cell.right();
+    }
+    let mut result = quasiquote(scope, &current, depth)?;
+    for item in items.into_iter().rev() {
+        result = Cell::new(item, result);
+    }
+    Ok(result)
+}
+
+// Evaluates a value. Calls in tail position, such as the last expression of a
+// lambda body or the branches of an if, continue around the loop instead of
+// recursing, so tail-recursive loops run in constant Rust stack.
+//
+// The lambda whose body is being evaluated is kept as the frame of this loop,
+// and is added to the backtrace of any error that escapes it. A tail call
+// replaces the frame of its caller, just as it reuses the loop.
 pub fn eval(scope: &Arc<Scope>, value: &Arc<Value>) -> Result<Arc<Value>, Error> {
-    match value.deref() {
-        Value::Nil | Value::Function(_) | Value::SpecialForm(_) => Ok(value.clone()),
-        Value::Symbol(name) => scope.lookup(name),
-        Value::Quoted(value) => Ok(value.clone()),
-        Value::Cell(_) => {
-            let args = value.to_args()?;
-            let op = eval(scope, &args[0])?;
-            match op.deref() {
-                Value::Function(function) => {
-                    let evaluated: Vec<Arc<Value>> = args
-                        .iter()
-                        .skip(1)
-                        .map(|value| eval(scope, value))
-                        .collect::<Result<Vec<Arc<Value>>, Error>>()?;
-                    function.call(&evaluated)
+    let mut frame = None;
+    eval_loop(scope, value, &mut frame).map_err(|err| match frame.as_deref() {
+        Some(Value::Function(function)) => err.with_frame(&function.name),
+        _ => err,
+    })
+}
+
+fn eval_loop(
+    scope: &Arc<Scope>,
+    value: &Arc<Value>,
+    frame: &mut Option<Arc<Value>>,
+) -> Result<Arc<Value>, Error> {
+    let mut scope = scope.clone();
+    let mut value = value.clone();
+    loop {
+        let next = match value.deref() {
+            Value::Nil
+            | Value::Bool(_)
+            | Value::Number(_)
+            | Value::String(_)
+            | Value::Function(_)
+            | Value::SpecialForm(_)
+            | Value::Condition(_)
+            | Value::Unassigned => return Ok(value.clone()),
+            Value::Symbol(name) => return scope.lookup(name),
+            Value::Alias(alias) => return alias.lookup(&scope),
+            Value::Local(local) => return scope.lookup_local(local),
+            Value::Quoted(value) => return Ok(value.clone()),
+            Value::Quasiquoted(template) => return quasiquote(&scope, template, 1),
+            Value::Unquoted(_) | Value::UnquoteSpliced(_) => {
+                return Err(Error::eval_error(format!(
+                    "Unquote outside of quasiquote: {}",
+                    to_string(&value)
+                )))
+            }
+            Value::Cell(_) => {
+                let args = value.to_args()?;
+                let op = eval(&scope, &args[0])?;
+                match op.deref() {
+                    Value::Function(function) => {
+                        let evaluated: Vec<Arc<Value>> = args
+                            .iter()
+                            .skip(1)
+                            .map(|value| eval(&scope, value))
+                            .collect::<Result<Vec<Arc<Value>>, Error>>()?;
+                        match &function.body {
+                            FuncBody::Lambda(lambda) => {
+                                let lambda_scope = lambda.bind_args(&function.name, &evaluated)?;
+                                *frame = Some(op.clone());
+                                lambda.enter(&lambda_scope)?
+                            }
+                            _ => function.tail_call(&evaluated)?,
+                        }
+                    }
+                    Value::SpecialForm(special_form) => {
+                        special_form.call(&scope, &value, &args[1..])?
+                    }
+                    _ => {
+                        return Err(Error::eval_error(format!(
+                            "Not a function: {}",
+                            to_string(&op)
+                        )))
+                    }
                 }
-                Value::SpecialForm(special_form) => special_form.call(scope, &args[1..]),
-                _ => Err(Error::EvalError(format!(
-                    "Not a function: {}",
-                    to_string(&op)
-                ))),
             }
+        };
+        match next {
+            Trampoline::Return(result) => return Ok(result),
+            Trampoline::TailCall(next_scope, next_value) => {
+                scope = next_scope;
+                value = next_value;
+            }
+        }
+    }
+}
+
+// Calls a function with arguments that are already evaluated.
+pub fn apply(function: &Arc<Value>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    match function.deref() {
+        Value::Function(function) => function.tail_call(args),
+        _ => Err(Error::eval_error(format!(
+            "Not a function: {}",
+            to_string(function)
+        ))),
+    }
+}
+
+// Evaluates a sequence of expressions in order. The last one is left to the
+// caller as a tail call, and an empty sequence evaluates to nil.
+pub fn eval_body(scope: &Arc<Scope>, body: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    eval_sequence(scope, body, Arc::clone)
+}
+
+// Evaluates a sequence as eval_body does, passing each expression through
+// prepare just before it is evaluated.
+fn eval_sequence(
+    scope: &Arc<Scope>,
+    body: &[Arc<Value>],
+    prepare: impl Fn(&Arc<Value>) -> Arc<Value>,
+) -> Result<Trampoline, Error> {
+    match body.split_last() {
+        Some((last, init)) => {
+            for value in init {
+                eval(scope, &prepare(value))?;
+            }
+            Ok(Trampoline::TailCall(scope.clone(), prepare(last)))
         }
+        None => Ok(Trampoline::Return(Value::nil())),
     }
 }
+
+// Readies a top-level expression for evaluation in scope: macros are
+// expanded, and then local variables are resolved to their addresses.
+pub fn prepare(scope: &Arc<Scope>, value: &Arc<Value>) -> Arc<Value> {
+    resolve::resolve(scope, &expand::expand(scope, value))
+}
+
+// Evaluates top-level expressions in order, returning the value of the last.
+// Each is prepared just before it is evaluated, so that it can use the
+// macros and globals defined by the ones before it.
+pub fn eval_program(scope: &Arc<Scope>, values: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    eval_sequence(scope, values, |value| prepare(scope, value))?.resolve()
+}
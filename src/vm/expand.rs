@@ -0,0 +1,199 @@
+use std::collections::HashSet;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::vm::values::*;
+use crate::vm::*;
+
+// Special forms whose arguments are data rather than code, so their uses are
+// left as they are.
+const OPAQUE_FORMS: [&str; 2] = ["quote", "syntax-rules"];
+
+// The value of the identifier at the head of form, if it has one and it is
+// bound. Looking up an identifier has no side effects, so this is safe to do
+// before the form is evaluated.
+fn head_value(scope: &Arc<Scope>, form: &Arc<Value>) -> Option<Arc<Value>> {
+    let Value::Cell(cell) = form.deref() else {
+        return None;
+    };
+    let head = cell.left();
+    head.identifier()?;
+    eval(scope, &head).ok()
+}
+
+// Expands form once if it is a use of a macro, or gives None if it is not.
+pub fn expand_once(scope: &Arc<Scope>, form: &Arc<Value>) -> Result<Option<Arc<Value>>, Error> {
+    let Some(head) = head_value(scope, form) else {
+        return Ok(None);
+    };
+    match head.deref() {
+        Value::SpecialForm(special_form) if special_form.is_macro() => {
+            let args = form.to_args()?;
+            special_form.expand(form, &args[1..]).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+// Expands form until its head is no longer a macro.
+pub fn expand_head(scope: &Arc<Scope>, form: &Arc<Value>) -> Result<Arc<Value>, Error> {
+    let mut form = form.clone();
+    while let Some(expansion) = expand_once(scope, &form)? {
+        form = expansion;
+    }
+    Ok(form)
+}
+
+// Rewrites form with every macro use in it expanded, ahead of evaluating it.
+// This only sees macros bound in scope when it runs, and skips the names that
+// form itself binds, so anything it cannot expand safely is left for eval to
+// expand when it is reached. A use whose expansion fails is left alone too, so
+// that the error is reported only if the use is actually evaluated.
+pub fn expand(scope: &Arc<Scope>, form: &Arc<Value>) -> Arc<Value> {
+    expand_form(scope, form, &HashSet::new())
+}
+
+fn expand_form(scope: &Arc<Scope>, form: &Arc<Value>, shadowed: &HashSet<Symbol>) -> Arc<Value> {
+    let Ok(items) = form.to_args() else {
+        return form.clone();
+    };
+    let Some(head) = items.first() else {
+        return form.clone();
+    };
+    let is_shadowed = head
+        .identifier()
+        .is_some_and(|name| shadowed.contains(&name));
+    let special_form = match head_value(scope, form).as_deref() {
+        Some(Value::SpecialForm(special_form)) if !is_shadowed => {
+            if special_form.is_macro() {
+                return match special_form.expand(form, &items[1..]) {
+                    Ok(expansion) => expand_form(scope, &expansion, shadowed),
+                    Err(_) => form.clone(),
+                };
+            }
+            if OPAQUE_FORMS.contains(&special_form.name.as_str()) {
+                return form.clone();
+            }
+            Some(special_form.name.clone())
+        }
+        _ => None,
+    };
+    let mut shadowed = shadowed.clone();
+    shadowed.extend(bound_names(special_form.as_deref(), &items));
+    let expanded = match special_form.as_deref() {
+        // The data lists that start case clauses are not code.
+        Some("case") => items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| match item.to_args() {
+                Ok(clause) if index > 1 && !clause.is_empty() => {
+                    let mut body = expand_list(scope, &clause[1..], &mut shadowed);
+                    body.insert(0, clause[0].clone());
+                    rebuild(item, &clause, body)
+                }
+                _ => expand_form(scope, item, &shadowed),
+            })
+            .collect(),
+        _ => expand_list(scope, &items, &mut shadowed),
+    };
+    rebuild(form, &items, expanded)
+}
+
+// Expands the forms of a list in order, where a define among them shadows
+// the name it binds for the forms after it.
+fn expand_list(
+    scope: &Arc<Scope>,
+    items: &[Arc<Value>],
+    shadowed: &mut HashSet<Symbol>,
+) -> Vec<Arc<Value>> {
+    items
+        .iter()
+        .map(|item| {
+            if let (Ok(parts), Some(Value::SpecialForm(special_form))) =
+                (item.to_args(), head_value(scope, item).as_deref())
+            {
+                if special_form.name == "define" || special_form.name == "define-syntax" {
+                    shadowed.extend(defined_name(&parts));
+                }
+            }
+            expand_form(scope, item, shadowed)
+        })
+        .collect()
+}
+
+fn defined_name(parts: &[Arc<Value>]) -> Option<Symbol> {
+    let target = parts.get(1)?;
+    match target.deref() {
+        Value::Cell(cell) => cell.left().identifier(),
+        _ => target.identifier(),
+    }
+}
+
+// The names the special form called name binds for the rest of the form.
+fn bound_names(name: Option<&str>, items: &[Arc<Value>]) -> Vec<Symbol> {
+    let names = |value: Option<&Arc<Value>>| -> Vec<Symbol> {
+        value
+            .and_then(|value| value.to_args().ok())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|binding| match binding.deref() {
+                Value::Cell(cell) => cell.left().identifier(),
+                _ => binding.identifier(),
+            })
+            .map(|name| match name.name().strip_prefix("...") {
+                Some(rest) if !rest.is_empty() => Symbol::intern(rest),
+                _ => name,
+            })
+            .collect()
+    };
+    match name {
+        Some("lambda" | "macro" | "define") => match items.get(1).map(|target| target.deref()) {
+            Some(Value::Cell(_)) => names(items.get(1)),
+            _ => Vec::new(),
+        },
+        Some("let" | "let*" | "letrec" | "letrec*") => match items.get(1) {
+            Some(name) if name.identifier().is_some() => {
+                let mut bound = names(items.get(2));
+                bound.extend(name.identifier());
+                bound
+            }
+            bindings => names(bindings),
+        },
+        // (guard (name clause...) body...)
+        Some("guard") => items
+            .get(1)
+            .and_then(|spec| spec.to_args().ok())
+            .and_then(|spec| spec.first()?.identifier())
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// A list with the rewritten items, or the original list if none of them
+// changed, so that forms that need no rewriting keep their identity.
+pub fn rebuild(form: &Arc<Value>, items: &[Arc<Value>], expanded: Vec<Arc<Value>>) -> Arc<Value> {
+    if items
+        .iter()
+        .zip(&expanded)
+        .all(|(item, expanded)| Arc::ptr_eq(item, expanded))
+    {
+        form.clone()
+    } else {
+        Cell::from_vec(expanded)
+    }
+}
+
+// (macroexpand-1 form) expands form once if it is a use of a macro, and
+// otherwise gives it back as it is.
+pub fn macroexpand_1(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let form = eval(scope, &args[0])?;
+    let expansion = expand_once(scope, &form)?.unwrap_or(form);
+    Ok(Trampoline::Return(expansion))
+}
+
+// (macroexpand form) expands form until it is no longer a use of a macro.
+pub fn macroexpand(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let form = eval(scope, &args[0])?;
+    Ok(Trampoline::Return(expand_head(scope, &form)?))
+}
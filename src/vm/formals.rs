@@ -0,0 +1,130 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::vm::*;
+
+const SPLAT_MARKER: &str = "...";
+
+#[derive(Debug, PartialEq)]
+enum Formal {
+    Required(Symbol),
+    // (name default), where default is evaluated when the argument is missing.
+    Optional(Symbol, Arc<Value>),
+    // ...name, which takes a list of all the remaining arguments.
+    Rest(Symbol),
+}
+
+// The parameter list of a lambda or macro, such as (a (b 10) ...rest).
+// Parameters are bound in order, so a default can refer to the parameters
+// before it.
+fn parse_formal(formal: &Arc<Value>) -> Result<Formal, Error> {
+    match (formal.identifier(), formal.deref()) {
+        // A rest parameter renamed by syntax-rules is bound under the fresh
+        // name of the parameter without its marker.
+        (_, Value::Alias(alias)) if alias.original.name().starts_with(SPLAT_MARKER) => {
+            Ok(Formal::Rest(alias.name.clone()))
+        }
+        (Some(name), _) => Ok(match name.name().strip_prefix(SPLAT_MARKER) {
+            Some(rest) => Formal::Rest(Symbol::intern(rest)),
+            None => Formal::Required(name),
+        }),
+        (None, Value::Cell(_)) => match formal.to_args()?.as_slice() {
+            [name, default] => match name.identifier() {
+                Some(name) => Ok(Formal::Optional(name, default.clone())),
+                None => Err(Error::type_error("a symbol", name)),
+            },
+            _ => Err(Error::type_error("a (name default) parameter", formal)),
+        },
+        _ => Err(Error::type_error("a symbol", formal)),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Formals {
+    formals: Vec<Formal>,
+}
+
+impl Formals {
+    // A required parameter cannot follow an optional one, since the optional
+    // one could then never be left out.
+    pub fn parse(formals: &Arc<Value>) -> Result<Formals, Error> {
+        let mut parsed = Vec::new();
+        for formal in formals.to_args()? {
+            let next = parse_formal(&formal)?;
+            if let (Some(Formal::Optional(..)), Formal::Required(_)) = (parsed.last(), &next) {
+                return Err(Error::type_error("an optional or rest parameter", &formal));
+            }
+            parsed.push(next);
+        }
+        Ok(Formals { formals: parsed })
+    }
+
+    // Formals that are all required, such as the names of a named let.
+    pub fn from_names(names: Vec<Symbol>) -> Formals {
+        Formals {
+            formals: names.into_iter().map(Formal::Required).collect(),
+        }
+    }
+
+    // The names the parameters are bound to, in order.
+    pub fn names(&self) -> Vec<Symbol> {
+        self.formals
+            .iter()
+            .map(|formal| match formal {
+                Formal::Required(name) | Formal::Optional(name, _) | Formal::Rest(name) => {
+                    name.clone()
+                }
+            })
+            .collect()
+    }
+
+    pub fn arity(&self) -> Arity {
+        let count =
+            |matches: fn(&Formal) -> bool| self.formals.iter().filter(|f| matches(f)).count();
+        let required = count(|formal| matches!(formal, Formal::Required(_)));
+        let optional = count(|formal| matches!(formal, Formal::Optional(..)));
+        if count(|formal| matches!(formal, Formal::Rest(_))) > 0 {
+            Arity::AtLeast(required)
+        } else if optional > 0 {
+            Arity::Between(required, required + optional)
+        } else {
+            Arity::Exactly(required)
+        }
+    }
+
+    // Binds the arguments in a new child of scope. The name is that of the
+    // function or macro, for reporting arity errors.
+    pub fn bind(
+        &self,
+        name: &str,
+        scope: &Arc<Scope>,
+        args: &[Arc<Value>],
+    ) -> Result<Arc<Scope>, Error> {
+        let arity_error = || Error::arity_error(self.arity(), args.len()).with_operator(name);
+        let scope = scope.new_child(Vec::new());
+        let mut args_iter = args.iter();
+        for formal in &self.formals {
+            match formal {
+                Formal::Required(name) => {
+                    let arg = args_iter.next().ok_or_else(arity_error)?;
+                    scope.bind(name.clone(), arg.clone());
+                }
+                Formal::Optional(name, default) => {
+                    let value = match args_iter.next() {
+                        Some(arg) => arg.clone(),
+                        None => eval(&scope, default)?,
+                    };
+                    scope.bind(name.clone(), value);
+                }
+                Formal::Rest(name) => {
+                    let values = args_iter.by_ref().cloned().collect();
+                    scope.bind(name.clone(), Cell::from_vec(values));
+                }
+            }
+        }
+        if args_iter.next().is_some() {
+            return Err(arity_error());
+        }
+        Ok(scope)
+    }
+}
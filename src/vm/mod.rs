@@ -1,22 +1,30 @@
+mod conditions;
+mod errors;
 mod eval;
+mod expand;
+mod formals;
+mod numbers;
 mod parser;
+mod predicates;
+mod resolve;
+mod strings;
+mod symbols;
+mod syntax_rules;
 mod values;
 
+pub use errors::*;
 pub use eval::*;
+pub use expand::expand;
+pub use formals::Formals;
+pub use numbers::Number;
 pub use parser::*;
+pub use symbols::{Symbol, UNINTERNED_PREFIX};
 pub use values::*;
 
-#[derive(Debug)]
-pub enum Error {
-    ParseError,
-    EvalError(String),
-    TypeError,
-    ArityError,
-    NotFoundError(String),
-}
-
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+
     use super::*;
 
     // FIXME: Test which are valid symbol characters.
@@ -45,12 +53,115 @@ mod test {
         assert_eq!(&value, &Value::nil());
     }
 
+    #[test]
+    fn parse_number_test() {
+        fn number(text: &str) -> Number {
+            match parse(text).unwrap().as_ref() {
+                Value::Number(number) => number.clone(),
+                value => panic!("not a number: {:?}", value),
+            }
+        }
+
+        assert_eq!(number("42"), Number::Fixnum(42));
+        assert_eq!(number("+7"), Number::Fixnum(7));
+        assert_eq!(number("-3"), Number::Fixnum(-3));
+        assert_eq!(number("2.5"), Number::Float(2.5));
+        assert_eq!(number(".5"), Number::Float(0.5));
+        assert_eq!(number("-1e3"), Number::Float(-1000.0));
+        assert_eq!(number("4/2"), Number::Fixnum(2));
+        assert!(matches!(number("-2/6"), Number::Rational(_)));
+        assert!(matches!(number("9223372036854775808"), Number::Bignum(_)));
+        assert_eq!(number("-9223372036854775808"), Number::Fixnum(i64::MIN));
+        assert_eq!(&parse("-").unwrap(), &Value::from_name("-"));
+        assert_eq!(&parse("1e").unwrap(), &Value::from_name("1e"));
+        assert_eq!(&parse("1/0").unwrap(), &Value::from_name("1/0"));
+        assert_eq!(&parse("1/-2").unwrap(), &Value::from_name("1/-2"));
+        assert_eq!(&parse("inf").unwrap(), &Value::from_name("inf"));
+        assert_eq!(number("+inf.0"), Number::Float(f64::INFINITY));
+        assert_eq!(number("-inf.0"), Number::Float(f64::NEG_INFINITY));
+        assert!(matches!(number("+nan.0"), Number::Float(value) if value.is_nan()));
+        assert_eq!(&parse("inf.0").unwrap(), &Value::from_name("inf.0"));
+    }
+
+    #[test]
+    fn parse_bool_test() {
+        assert_eq!(&parse("#t").unwrap(), &Value::from_bool(true));
+        assert_eq!(&parse("#false").unwrap(), &Value::from_bool(false));
+        assert_eq!(to_string(&parse("(#t #f)").unwrap()), "(#t #f)");
+    }
+
+    #[test]
+    fn parse_string_test() {
+        let value = parse(r#""a\tb\"c\\d\u{263A}""#).unwrap();
+        assert_eq!(
+            &value,
+            &Arc::new(Value::String("a\tb\"c\\d\u{263A}".to_string()))
+        );
+        assert_eq!(to_string(&value), r#""a\tb\"c\\d☺""#);
+
+        let value = parse(r#"(a"b c"d)"#).unwrap();
+        assert_eq!(to_string(&value), r#"(a "b c" d)"#);
+
+        assert!(parse(r#""abc"#).is_err());
+        assert!(parse(r#""\u{zz}""#).is_err());
+    }
+
+    #[test]
+    fn parse_comment_test() {
+        let value = parse("; comment\n(a #| b |# c) ; trailing").unwrap();
+        assert_eq!(to_string(&value), "(a c)");
+
+        let value = parse("(a #;(b c) d #;e)").unwrap();
+        assert_eq!(to_string(&value), "(a d)");
+
+        assert!(parse("#| unterminated").is_err());
+        assert!(parse("#;a").is_err());
+        assert!(parse("(a #;)").is_err());
+    }
+
     #[test]
     fn parse_error_test() {
         let result = parse("a b");
         assert!(result.is_err());
     }
 
+    #[test]
+    fn parse_program_test() {
+        let values = parse_program("a (b c) ; comment\n 'd").unwrap();
+        let printed: Vec<String> = values.iter().map(to_string).collect();
+        assert_eq!(printed, vec!["a", "(b c)", "'d"]);
+
+        assert!(parse_program("").unwrap().is_empty());
+        assert!(parse_program("a (b").is_err());
+    }
+
+    #[test]
+    fn source_map_test() {
+        let (values, source_map) =
+            parse_program_with_source_map("lib.lisp", "a\n  (b \"c\")").unwrap();
+        let span = source_map.span(&values[1]).unwrap();
+        assert_eq!(span.to_string(), "lib.lisp:2:3");
+        assert_eq!((span.start, span.end), (4, 11));
+
+        let inner = values[1].clone();
+        let Value::Cell(cell) = inner.as_ref() else {
+            panic!("expected a list");
+        };
+        let span = source_map.span(&cell.left()).unwrap();
+        assert_eq!((span.line, span.column), (2, 4));
+
+        match parse_program_with_source_map("lib.lisp", "(a\n b))") {
+            Err(Error {
+                kind: ErrorKind::ParseError(err),
+                ..
+            }) => {
+                assert_eq!(err.message, "unexpected ')'");
+                assert_eq!(err.span.to_string(), "lib.lisp:2:4");
+            }
+            result => panic!("expected a parse error, got {:?}", result),
+        }
+    }
+
     #[test]
     fn parse_and_print_test() {
         let value = parse("(a)").unwrap();
@@ -67,5 +178,195 @@ mod test {
 
         let value = parse("(a '(b c))").unwrap();
         assert_eq!(to_string(&value), "(a '(b c))");
+
+        let value = parse("`(a ,b ,@(c d) `,e)").unwrap();
+        assert_eq!(to_string(&value), "`(a ,b ,@(c d) `,e)");
+
+        let value = parse("(a,b)").unwrap();
+        assert_eq!(to_string(&value), "(a ,b)");
+    }
+
+    #[test]
+    fn cycle_test() {
+        // The list of items with its last cdr pointing back to its start.
+        fn cycle(items: &str) -> Arc<Value> {
+            let list = parse(items).unwrap();
+            let mut last = list.clone();
+            while let Value::Cell(cell) = last.clone().as_ref() {
+                if !matches!(cell.right().as_ref(), Value::Cell(_)) {
+                    cell.set_right(list.clone());
+                    break;
+                }
+                last = cell.right();
+            }
+            list
+        }
+
+        let (a, b) = (cycle("(1 2)"), cycle("(1 2 1 2)"));
+        assert_eq!(&a, &b);
+        assert_ne!(&a, &cycle("(1 3)"));
+        assert_eq!(to_string(&a), "(1 2 . #cycle)");
+        assert!(format!("{:?}", a).contains("#cycle"));
+    }
+
+    #[test]
+    fn symbol_test() {
+        assert_eq!(Symbol::intern("a"), Symbol::intern("a"));
+        assert_ne!(Symbol::intern("a"), Symbol::intern("b"));
+        assert_eq!(Symbol::intern("a").name().as_ref(), "a");
+
+        let uninterned = Symbol::uninterned("a");
+        assert_ne!(uninterned, Symbol::intern("a"));
+        assert_ne!(uninterned, Symbol::uninterned("a"));
+        assert_eq!(uninterned, uninterned.clone());
+        assert_eq!(uninterned.to_string(), "#:a");
+    }
+
+    #[test]
+    fn resolve_test() {
+        let scope = Scope::builtin();
+        let form = prepare(
+            &scope,
+            &parse("(lambda (a b) (lambda (c) (list b c)))").unwrap(),
+        );
+        let body = form.to_args().unwrap()[2].to_args().unwrap()[2]
+            .to_args()
+            .unwrap();
+        let locals: Vec<_> = body[1..]
+            .iter()
+            .map(|value| match value.as_ref() {
+                Value::Local(local) => (local.symbol.to_string(), local.depth, local.index),
+                value => panic!("not a local: {:?}", value),
+            })
+            .collect();
+        assert_eq!(
+            locals,
+            vec![("b".to_string(), 1, 1), ("c".to_string(), 0, 0)]
+        );
+        assert!(matches!(body[0].as_ref(), Value::Symbol(_)));
+    }
+
+    // The local variables in a prepared form, in order, with the depth and
+    // index resolve gave each.
+    fn locals(source: &str) -> Vec<(String, usize, usize)> {
+        fn walk(value: &Arc<Value>, found: &mut Vec<(String, usize, usize)>) {
+            match value.as_ref() {
+                Value::Local(local) => {
+                    found.push((local.symbol.to_string(), local.depth, local.index))
+                }
+                Value::Cell(_) => {
+                    for item in value.to_args().unwrap() {
+                        walk(&item, found);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let scope = Scope::builtin();
+        let mut found = Vec::new();
+        walk(&prepare(&scope, &parse(source).unwrap()), &mut found);
+        found
+    }
+
+    fn local(name: &str, depth: usize, index: usize) -> (String, usize, usize) {
+        (name.to_string(), depth, index)
+    }
+
+    // The printed value of a program, and how many local variables evaluating
+    // it looked up or set by name instead of by address.
+    fn eval_counting_fallbacks(source: &str) -> (String, usize) {
+        let scope = Scope::builtin();
+        let before = local_fallbacks();
+        let value = eval_program(&scope, &parse_program(source).unwrap()).unwrap();
+        (to_string(&value), local_fallbacks() - before)
+    }
+
+    #[test]
+    fn resolve_recursive_define_test() {
+        assert_eq!(
+            locals("(define (f a) (lambda (b) (f b a)))"),
+            vec![local("b", 0, 0), local("a", 1, 0)]
+        );
+    }
+
+    #[test]
+    fn resolve_scopes_test() {
+        assert_eq!(
+            locals("(let* ((a 1) (b a)) (list a b))"),
+            vec![local("a", 0, 0), local("a", 1, 0), local("b", 0, 0)]
+        );
+        assert_eq!(
+            locals("(let loop ((i 0) (acc '())) (if (< i 2) (loop (+ i 1) (cons i acc)) acc))"),
+            vec![
+                local("i", 0, 0),
+                local("loop", 1, 0),
+                local("i", 0, 0),
+                local("i", 0, 0),
+                local("acc", 0, 1),
+                local("acc", 0, 1),
+            ]
+        );
+        assert_eq!(
+            locals(
+                "(letrec ((ev? (lambda (n) (if (= n 0) #t (od? (- n 1)))))
+                          (od? (lambda (n) (if (= n 0) #f (ev? (- n 1))))))
+                   (ev? 4))"
+            ),
+            vec![
+                local("n", 0, 0),
+                local("od?", 1, 1),
+                local("n", 0, 0),
+                local("n", 0, 0),
+                local("ev?", 1, 0),
+                local("n", 0, 0),
+                local("ev?", 0, 0),
+            ]
+        );
+        assert_eq!(
+            locals("(lambda (x) (guard (e (#t (list e x))) (raise x)))"),
+            vec![local("e", 0, 0), local("x", 1, 0), local("x", 0, 0)]
+        );
+        assert_eq!(
+            locals("(define (f x) (define y (* x 2)) (+ x y))"),
+            vec![local("x", 0, 0), local("x", 0, 0), local("y", 0, 1)]
+        );
+        // b is given the slot after a, which it is only in if a is defined.
+        assert_eq!(
+            locals("(define (g c) (if c (define a 1)) (define b 2) b)"),
+            vec![local("c", 0, 0), local("b", 0, 2)]
+        );
+    }
+
+    #[test]
+    fn local_fallback_test() {
+        for (source, value) in [
+            ("(let* ((a 1) (b a)) (set! b (+ a b)) (list a b))", "(1 2)"),
+            (
+                "(let loop ((i 0) (acc '())) (if (< i 2) (loop (+ i 1) (cons i acc)) acc))",
+                "(1 0)",
+            ),
+            (
+                "(letrec ((ev? (lambda (n) (if (= n 0) #t (od? (- n 1)))))
+                          (od? (lambda (n) (if (= n 0) #f (ev? (- n 1))))))
+                   (ev? 4))",
+                "#t",
+            ),
+            (
+                "((lambda (x) (guard (e (#t (list e x))) (raise x))) 1)",
+                "(1 1)",
+            ),
+            ("(define (f x) (define y (* x 2)) (+ x y)) (f 3)", "9"),
+            (
+                "(define (g c) (if c (define a 1)) (define b 2) b) (g #t)",
+                "2",
+            ),
+        ] {
+            assert_eq!(eval_counting_fallbacks(source), (value.to_string(), 0));
+        }
+        // When the conditional define does not run, b is in a's slot.
+        assert_eq!(
+            eval_counting_fallbacks("(define (g c) (if c (define a 1)) (define b 2) b) (g #f)"),
+            ("2".to_string(), 1)
+        );
     }
 }
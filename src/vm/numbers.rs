@@ -0,0 +1,328 @@
+use std::cmp::Ordering;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+
+use crate::vm::values::*;
+use crate::vm::{Arity, Error};
+
+// The numeric tower, from narrowest to widest. Exact results are always kept
+// in their narrowest representation: a Bignum never fits in an i64 and a
+// Rational never has a denominator of one. That keeps the derived PartialEq
+// meaningful and lets the Fixnum fast paths cover the common case.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Number {
+    Fixnum(i64),
+    Bignum(BigInt),
+    Rational(BigRational),
+    Float(f64),
+}
+
+impl Number {
+    fn from_bigint(value: BigInt) -> Number {
+        match value.to_i64() {
+            Some(value) => Number::Fixnum(value),
+            None => Number::Bignum(value),
+        }
+    }
+
+    fn from_rational(value: BigRational) -> Number {
+        if value.is_integer() {
+            Number::from_bigint(value.to_integer())
+        } else {
+            Number::Rational(value)
+        }
+    }
+
+    // Parses integer, rational (`1/3`) and decimal literals, and the
+    // infinities and NaN as they are printed. Anything else, like `-` or
+    // `...rest`, is left for the tokenizer to treat as a symbol.
+    pub fn parse(text: &str) -> Option<Number> {
+        fn is_digits(text: &str) -> bool {
+            !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_digit())
+        }
+
+        match text {
+            "+inf.0" => return Some(Number::Float(f64::INFINITY)),
+            "-inf.0" => return Some(Number::Float(f64::NEG_INFINITY)),
+            "+nan.0" | "-nan.0" => return Some(Number::Float(f64::NAN)),
+            _ => {}
+        }
+
+        let unsigned = text.strip_prefix(['+', '-']).unwrap_or(text);
+        if !unsigned.starts_with(|ch: char| ch.is_ascii_digit() || ch == '.') {
+            return None;
+        }
+        if is_digits(unsigned) {
+            return BigInt::from_str(text).ok().map(Number::from_bigint);
+        }
+        if let Some((numerator, denominator)) = text.split_once('/') {
+            let unsigned_numerator = numerator.strip_prefix(['+', '-']).unwrap_or(numerator);
+            if !is_digits(unsigned_numerator) || !is_digits(denominator) {
+                return None;
+            }
+            let numerator = BigInt::from_str(numerator).ok()?;
+            let denominator = BigInt::from_str(denominator).ok()?;
+            if denominator.is_zero() {
+                return None;
+            }
+            return Some(Number::from_rational(BigRational::new(
+                numerator,
+                denominator,
+            )));
+        }
+        text.parse::<f64>().ok().map(Number::Float)
+    }
+
+    fn from_value(value: &Arc<Value>) -> Result<Number, Error> {
+        match value.deref() {
+            Value::Number(number) => Ok(number.clone()),
+            _ => Err(Error::type_error("a number", value)),
+        }
+    }
+
+    fn into_value(self) -> Arc<Value> {
+        Arc::new(Value::Number(self))
+    }
+
+    fn is_exact(&self) -> bool {
+        !matches!(self, Number::Float(_))
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Number::Fixnum(value) => *value == 0,
+            Number::Bignum(value) => value.is_zero(),
+            Number::Rational(value) => value.is_zero(),
+            Number::Float(value) => *value == 0.0,
+        }
+    }
+
+    fn to_bigint(&self) -> BigInt {
+        match self {
+            Number::Fixnum(value) => BigInt::from(*value),
+            Number::Bignum(value) => value.clone(),
+            _ => unreachable!("not an integer: {}", self),
+        }
+    }
+
+    fn to_rational(&self) -> BigRational {
+        match self {
+            Number::Rational(value) => value.clone(),
+            Number::Float(_) => unreachable!("not exact: {}", self),
+            _ => BigRational::from_integer(self.to_bigint()),
+        }
+    }
+
+    // The exact value of a number, which infinities and NaN do not have.
+    fn to_exact(&self) -> Option<BigRational> {
+        match self {
+            Number::Float(value) => BigRational::from_float(*value),
+            _ => Some(self.to_rational()),
+        }
+    }
+
+    fn to_float(&self) -> f64 {
+        match self {
+            Number::Fixnum(value) => *value as f64,
+            Number::Bignum(value) => value.to_f64().unwrap_or(f64::NAN),
+            Number::Rational(value) => value.to_f64().unwrap_or(f64::NAN),
+            Number::Float(value) => *value,
+        }
+    }
+
+    // Both operands are widened to the wider of their two representations.
+    // Fixnum arithmetic that overflows is redone with bignums rather than
+    // wrapping or panicking.
+    fn combine(&self, other: &Number, ops: &Ops) -> Number {
+        match (self, other) {
+            (Number::Fixnum(lhs), Number::Fixnum(rhs)) => match (ops.fixnum)(*lhs, *rhs) {
+                Some(value) => Number::Fixnum(value),
+                None => Number::from_bigint((ops.bignum)(&self.to_bigint(), &other.to_bigint())),
+            },
+            (Number::Float(_), _) | (_, Number::Float(_)) => {
+                Number::Float((ops.float)(self.to_float(), other.to_float()))
+            }
+            (Number::Rational(_), _) | (_, Number::Rational(_)) => {
+                Number::from_rational((ops.rational)(&self.to_rational(), &other.to_rational()))
+            }
+            _ => Number::from_bigint((ops.bignum)(&self.to_bigint(), &other.to_bigint())),
+        }
+    }
+
+    fn add(self, other: Number) -> Result<Number, Error> {
+        Ok(self.combine(
+            &other,
+            &Ops {
+                fixnum: i64::checked_add,
+                bignum: |lhs, rhs| lhs + rhs,
+                rational: |lhs, rhs| lhs + rhs,
+                float: |lhs, rhs| lhs + rhs,
+            },
+        ))
+    }
+
+    fn sub(self, other: Number) -> Result<Number, Error> {
+        Ok(self.combine(
+            &other,
+            &Ops {
+                fixnum: i64::checked_sub,
+                bignum: |lhs, rhs| lhs - rhs,
+                rational: |lhs, rhs| lhs - rhs,
+                float: |lhs, rhs| lhs - rhs,
+            },
+        ))
+    }
+
+    fn mul(self, other: Number) -> Result<Number, Error> {
+        Ok(self.combine(
+            &other,
+            &Ops {
+                fixnum: i64::checked_mul,
+                bignum: |lhs, rhs| lhs * rhs,
+                rational: |lhs, rhs| lhs * rhs,
+                float: |lhs, rhs| lhs * rhs,
+            },
+        ))
+    }
+
+    // Dividing exact numbers always gives an exact result, so integers that do
+    // not divide evenly produce a rational.
+    fn div(self, other: Number) -> Result<Number, Error> {
+        if !self.is_exact() || !other.is_exact() {
+            return Ok(Number::Float(self.to_float() / other.to_float()));
+        }
+        if other.is_zero() {
+            return Err(Error::eval_error("Division by zero".to_string()));
+        }
+        Ok(Number::from_rational(
+            self.to_rational() / other.to_rational(),
+        ))
+    }
+
+    fn compare(&self, other: &Number) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Fixnum(lhs), Number::Fixnum(rhs)) => Some(lhs.cmp(rhs)),
+            (Number::Float(lhs), Number::Float(rhs)) => lhs.partial_cmp(rhs),
+            // A finite float is compared with an exact number by its exact
+            // value, since rounding the exact number to a float would make =
+            // intransitive. Infinities and NaN compare with any exact number
+            // as they do with zero.
+            _ => match (self.to_exact(), other.to_exact()) {
+                (Some(lhs), Some(rhs)) => Some(lhs.cmp(&rhs)),
+                (Some(_), None) => 0.0.partial_cmp(&other.to_float()),
+                (None, _) => self.to_float().partial_cmp(&0.0),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Number::Fixnum(value) => write!(f, "{}", value),
+            Number::Bignum(value) => write!(f, "{}", value),
+            Number::Rational(value) => write!(f, "{}", value),
+            Number::Float(value) if value.is_nan() => write!(f, "+nan.0"),
+            Number::Float(value) if value.is_infinite() => {
+                write!(f, "{}inf.0", if *value > 0.0 { '+' } else { '-' })
+            }
+            // Debug formatting keeps the decimal point, so 3.0 does not print as 3.
+            Number::Float(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+// The same operation at each level of the tower. The fixnum version returns
+// None on overflow.
+struct Ops {
+    fixnum: fn(i64, i64) -> Option<i64>,
+    bignum: fn(&BigInt, &BigInt) -> BigInt,
+    rational: fn(&BigRational, &BigRational) -> BigRational,
+    float: fn(f64, f64) -> f64,
+}
+
+fn to_numbers(args: &[Arc<Value>]) -> Result<Vec<Number>, Error> {
+    args.iter().map(Number::from_value).collect()
+}
+
+fn fold(
+    args: &[Arc<Value>],
+    initial: Number,
+    op: fn(Number, Number) -> Result<Number, Error>,
+) -> Result<Arc<Value>, Error> {
+    let mut result = initial;
+    for number in to_numbers(args)? {
+        result = op(result, number)?;
+    }
+    Ok(result.into_value())
+}
+
+// With a single argument, - and / apply to the identity element, so (- x) is
+// the negation of x and (/ x) is its reciprocal.
+fn fold_inverse(
+    args: &[Arc<Value>],
+    identity: Number,
+    op: fn(Number, Number) -> Result<Number, Error>,
+) -> Result<Arc<Value>, Error> {
+    Arity::AtLeast(1).check(args.len())?;
+    let mut numbers = to_numbers(args)?.into_iter();
+    let first = numbers.next().unwrap();
+    if numbers.len() == 0 {
+        return Ok(op(identity, first)?.into_value());
+    }
+    let mut result = first;
+    for number in numbers {
+        result = op(result, number)?;
+    }
+    Ok(result.into_value())
+}
+
+fn compare_chain(args: &[Arc<Value>], accept: fn(Ordering) -> bool) -> Result<Arc<Value>, Error> {
+    Arity::AtLeast(1).check(args.len())?;
+    let numbers = to_numbers(args)?;
+    let holds = numbers
+        .windows(2)
+        .all(|pair| pair[0].compare(&pair[1]).is_some_and(accept));
+    Ok(Value::from_bool(holds))
+}
+
+pub fn add(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    fold(args, Number::Fixnum(0), Number::add)
+}
+
+pub fn sub(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    fold_inverse(args, Number::Fixnum(0), Number::sub)
+}
+
+pub fn mul(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    fold(args, Number::Fixnum(1), Number::mul)
+}
+
+pub fn div(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    fold_inverse(args, Number::Fixnum(1), Number::div)
+}
+
+pub fn less(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    compare_chain(args, Ordering::is_lt)
+}
+
+pub fn greater(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    compare_chain(args, Ordering::is_gt)
+}
+
+pub fn equal(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    compare_chain(args, Ordering::is_eq)
+}
+
+pub fn less_or_equal(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    compare_chain(args, Ordering::is_le)
+}
+
+pub fn greater_or_equal(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    compare_chain(args, Ordering::is_ge)
+}
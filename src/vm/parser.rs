@@ -1,122 +1,473 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::vm::values::*;
-use crate::vm::Error;
+use crate::vm::{Error, Number, Symbol, UNINTERNED_PREFIX};
 
-#[derive(Debug, PartialEq, Eq)]
+// Where a parsed value came from: the line and column (both counted from 1)
+// where it starts, and its byte range in the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: Arc<str>,
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+// Spans of parsed values, keyed by the identity of each value. The map holds
+// on to the values so that their addresses cannot be reused by others.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    spans: HashMap<*const Value, (Arc<Value>, Span)>,
+}
+
+impl SourceMap {
+    fn insert(&mut self, value: &Arc<Value>, span: Span) {
+        self.spans.insert(Arc::as_ptr(value), (value.clone(), span));
+    }
+
+    pub fn span(&self, value: &Arc<Value>) -> Option<&Span> {
+        self.spans.get(&Arc::as_ptr(value)).map(|(_, span)| span)
+    }
+}
+
+#[derive(Debug, PartialEq)]
 enum Token {
     Symbol(String),
+    Number(Number),
+    String(String),
+    Bool(bool),
     OpenParen,
     CloseParen,
     QuoteMark,
+    Backquote,
+    Comma,
+    CommaAt,
+    // `#;`, which comments out the next whole expression.
+    DatumComment,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Symbol(name) => format!("symbol {}", name),
+            Token::Number(number) => format!("number {}", number),
+            Token::String(_) => "string literal".to_string(),
+            Token::Bool(value) => format!("boolean {}", if *value { "#t" } else { "#f" }),
+            Token::OpenParen => "'('".to_string(),
+            Token::CloseParen => "')'".to_string(),
+            Token::QuoteMark => "quote".to_string(),
+            Token::Backquote => "quasiquote".to_string(),
+            Token::Comma => "unquote".to_string(),
+            Token::CommaAt => "unquote-splicing".to_string(),
+            Token::DatumComment => "'#;'".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    offset: usize,
+    line: usize,
+    column: usize,
 }
 
 struct Tokenizer<'a> {
+    file: Arc<str>,
     input: &'a str,
-    position: usize,
+    // Position of the next character in `input`.
+    position: Position,
     buffer: String,
-    peeked: Option<Token>,
+    // Position of the first character in `buffer`.
+    buffer_start: Position,
+    peeked: Option<(Token, Span)>,
 }
 
 impl<'a> Tokenizer<'a> {
-    fn new(input: &str) -> Tokenizer {
+    fn new(file: &str, input: &'a str) -> Tokenizer<'a> {
+        let start = Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        };
         Tokenizer {
+            file: file.into(),
             input,
-            position: 0,
+            position: start,
             buffer: String::new(),
+            buffer_start: start,
             peeked: None,
         }
     }
 
+    fn peek_char(&self) -> Option<char> {
+        self.input[self.position.offset..].chars().next()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let ch = self.peek_char()?;
+        self.position.offset += ch.len_utf8();
+        if ch == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(ch)
+    }
+
+    // The span from `start` up to the current position.
+    fn span(&self, start: Position) -> Span {
+        Span {
+            file: self.file.clone(),
+            line: start.line,
+            column: start.column,
+            start: start.offset,
+            end: self.position.offset,
+        }
+    }
+
+    fn error(&self, message: String, start: Position) -> Error {
+        Error::from(ParseError {
+            message,
+            span: self.span(start),
+        })
+    }
+
     fn take_buffer(&mut self) -> String {
         let mut string = String::new();
         std::mem::swap(&mut string, &mut self.buffer);
-        return string;
+        string
+    }
+
+    fn take_atom(&mut self) -> (Token, Span) {
+        let text = self.take_buffer();
+        let token = match text.as_str() {
+            "#t" | "#true" => Token::Bool(true),
+            "#f" | "#false" => Token::Bool(false),
+            _ => match Number::parse(&text) {
+                Some(number) => Token::Number(number),
+                None => Token::Symbol(text),
+            },
+        };
+        (token, self.span(self.buffer_start))
     }
 
-    fn peek(&mut self) -> &Option<Token> {
+    fn peek(&mut self) -> Result<Option<&Token>, Error> {
         if self.peeked.is_none() {
-            self.peeked = self.next();
+            self.peeked = self.next()?;
         }
-        return &self.peeked;
+        Ok(self.peeked.as_ref().map(|(token, _)| token))
     }
 
-    fn next(&mut self) -> Option<Token> {
+    // An empty span at the current position, for errors at the end of input.
+    fn end_span(&self) -> Span {
+        self.span(self.position)
+    }
+
+    fn next(&mut self) -> Result<Option<(Token, Span)>, Error> {
         if let Some(token) = self.peeked.take() {
-            return Some(token);
+            return Ok(Some(token));
         }
-        while let Some(ch) = self.input.chars().nth(self.position) {
-            self.position += 1;
-            match ch {
-                '(' => {
-                    if self.buffer.is_empty() {
-                        return Some(Token::OpenParen);
-                    } else {
-                        self.position -= 1;
-                        return Some(Token::Symbol(self.take_buffer()));
-                    }
+        while let Some(ch) = self.peek_char() {
+            if !self.buffer.is_empty() && is_delimiter(ch) {
+                return Ok(Some(self.take_atom()));
+            }
+            let start = self.position;
+            self.next_char();
+            let token = match ch {
+                '(' => Token::OpenParen,
+                ')' => Token::CloseParen,
+                '\'' => Token::QuoteMark,
+                '`' => Token::Backquote,
+                ',' if self.peek_char() == Some('@') => {
+                    self.next_char();
+                    Token::CommaAt
                 }
-                ')' => {
-                    if self.buffer.is_empty() {
-                        return Some(Token::CloseParen);
-                    } else {
-                        self.position -= 1;
-                        return Some(Token::Symbol(self.take_buffer()));
-                    }
+                ',' => Token::Comma,
+                '"' => self.read_string(start)?,
+                ';' => {
+                    self.skip_line_comment();
+                    continue;
                 }
-                '\'' => {
-                    if self.buffer.is_empty() {
-                        return Some(Token::QuoteMark);
-                    } else {
-                        self.position -= 1;
-                        return Some(Token::Symbol(self.take_buffer()));
-                    }
+                '#' if self.buffer.is_empty() && self.peek_char() == Some('|') => {
+                    self.next_char();
+                    self.skip_block_comment(start)?;
+                    continue;
                 }
-                ' ' | '\t' | '\n' | '\r' => {
-                    if !self.buffer.is_empty() {
-                        return Some(Token::Symbol(self.take_buffer()));
+                '#' if self.buffer.is_empty() && self.peek_char() == Some(';') => {
+                    self.next_char();
+                    Token::DatumComment
+                }
+                ' ' | '\t' | '\n' | '\r' => continue,
+                _ => {
+                    if self.buffer.is_empty() {
+                        self.buffer_start = start;
                     }
+                    self.buffer.push(ch);
+                    continue;
                 }
-                _ => self.buffer.push(ch),
             };
+            return Ok(Some((token, self.span(start))));
         }
         if !self.buffer.is_empty() {
-            return Some(Token::Symbol(self.take_buffer()));
+            return Ok(Some(self.take_atom()));
+        }
+        Ok(None)
+    }
+
+    fn skip_line_comment(&mut self) {
+        while let Some(ch) = self.next_char() {
+            if ch == '\n' {
+                break;
+            }
+        }
+    }
+
+    // Skips the rest of a `#| ... |#` comment, after the opening `#|`. Block
+    // comments nest, so each `#|` inside needs its own `|#`.
+    fn skip_block_comment(&mut self, start: Position) -> Result<(), Error> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next_char() {
+                Some('|') if self.peek_char() == Some('#') => {
+                    self.next_char();
+                    depth -= 1;
+                }
+                Some('#') if self.peek_char() == Some('|') => {
+                    self.next_char();
+                    depth += 1;
+                }
+                Some(_) => {}
+                None => {
+                    return Err(self.error("unterminated block comment".to_string(), start));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Reads the rest of a string literal, after the opening quote.
+    fn read_string(&mut self, start: Position) -> Result<Token, Error> {
+        let mut string = String::new();
+        loop {
+            let escape_start = self.position;
+            match self.next_char() {
+                Some('"') => return Ok(Token::String(string)),
+                Some('\\') => string.push(self.read_escape(escape_start)?),
+                Some(ch) => string.push(ch),
+                None => {
+                    return Err(self.error("unterminated string literal".to_string(), start));
+                }
+            }
+        }
+    }
+
+    fn read_escape(&mut self, start: Position) -> Result<char, Error> {
+        match self.next_char() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('0') => Ok('\0'),
+            Some('"') => Ok('"'),
+            Some('\\') => Ok('\\'),
+            Some('u') => {
+                let mut digits = String::new();
+                if self.next_char() == Some('{') {
+                    while let Some(ch) = self.next_char() {
+                        if ch == '}' {
+                            return u32::from_str_radix(&digits, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| {
+                                    self.error(
+                                        format!("invalid unicode escape \\u{{{}}}", digits),
+                                        start,
+                                    )
+                                });
+                        }
+                        digits.push(ch);
+                    }
+                }
+                Err(self.error(
+                    "expected \\u{...} with hexadecimal digits".to_string(),
+                    start,
+                ))
+            }
+            Some(ch) => Err(self.error(format!("unknown escape sequence \\{}", ch), start)),
+            None => Err(self.error("unterminated string literal".to_string(), start)),
         }
-        return None;
     }
 }
 
-fn parse_value(tokenizer: &mut Tokenizer) -> Result<Arc<Value>, Error> {
-    if let Some(token) = tokenizer.next() {
+fn is_delimiter(ch: char) -> bool {
+    matches!(
+        ch,
+        '(' | ')' | '\'' | '`' | ',' | '"' | ';' | ' ' | '\t' | '\n' | '\r'
+    )
+}
+
+struct Parser<'a> {
+    tokenizer: Tokenizer<'a>,
+    source_map: SourceMap,
+}
+
+impl<'a> Parser<'a> {
+    fn new(file: &str, input: &'a str) -> Parser<'a> {
+        Parser {
+            tokenizer: Tokenizer::new(file, input),
+            source_map: SourceMap::default(),
+        }
+    }
+
+    fn record(&mut self, value: Value, span: Span) -> (Arc<Value>, Span) {
+        let value = Arc::new(value);
+        self.source_map.insert(&value, span.clone());
+        (value, span)
+    }
+
+    fn end_of_input(&self, expected: &str) -> Error {
+        Error::from(ParseError {
+            message: format!("unexpected end of input, expected {}", expected),
+            span: self.tokenizer.end_span(),
+        })
+    }
+
+    // Drops any `#;` comments, along with the expression each one comments out.
+    fn skip_datum_comments(&mut self) -> Result<(), Error> {
+        while self.tokenizer.peek()? == Some(&Token::DatumComment) {
+            self.tokenizer.next()?;
+            self.parse_value("an expression after '#;'")?;
+        }
+        Ok(())
+    }
+
+    // Parses one expression. `expected` describes it for the error reported
+    // if the input ends first.
+    fn parse_value(&mut self, expected: &str) -> Result<(Arc<Value>, Span), Error> {
+        self.skip_datum_comments()?;
+        let (token, span) = match self.tokenizer.next()? {
+            Some(next) => next,
+            None => return Err(self.end_of_input(expected)),
+        };
         match token {
             Token::OpenParen => {
                 let mut values = Vec::new();
-                while *tokenizer.peek() != Some(Token::CloseParen) {
-                    let value = parse_value(tokenizer)?;
+                loop {
+                    self.skip_datum_comments()?;
+                    match self.tokenizer.peek()? {
+                        Some(Token::CloseParen) => break,
+                        Some(_) => {}
+                        None => {
+                            return Err(self.end_of_input(&format!(
+                                "')' to close the '(' at {}:{}",
+                                span.line, span.column
+                            )))
+                        }
+                    }
+                    let (value, _) = self.parse_value("an expression")?;
                     values.push(value);
                 }
-                let close_paren = tokenizer.next().unwrap();
-                assert_eq!(close_paren, Token::CloseParen);
-                return Ok(Cell::from_vec(values));
+                let (_, close_span) = self.tokenizer.next()?.unwrap();
+                let span = Span {
+                    end: close_span.end,
+                    ..span
+                };
+                let list = Cell::from_vec(values);
+                self.source_map.insert(&list, span.clone());
+                Ok((list, span))
+            }
+            Token::CloseParen | Token::DatumComment => Err(Error::from(ParseError {
+                message: format!("unexpected {}", token.describe()),
+                span,
+            })),
+            Token::QuoteMark | Token::Backquote | Token::Comma | Token::CommaAt => {
+                let expected = format!("an expression after {}", token.describe());
+                let (value, value_span) = self.parse_value(&expected)?;
+                let span = Span {
+                    end: value_span.end,
+                    ..span
+                };
+                let value = match token {
+                    Token::QuoteMark => Value::Quoted(value),
+                    Token::Backquote => Value::Quasiquoted(value),
+                    Token::Comma => Value::Unquoted(value),
+                    _ => Value::UnquoteSpliced(value),
+                };
+                Ok(self.record(value, span))
+            }
+            Token::Symbol(name) if name.starts_with(UNINTERNED_PREFIX) => {
+                Err(Error::from(ParseError {
+                    message: format!("cannot read the uninterned symbol {}", name),
+                    span,
+                }))
             }
-            Token::CloseParen => Err(Error::ParseError),
-            Token::QuoteMark => {
-                let value = parse_value(tokenizer)?;
-                Ok(Arc::new(Value::Quoted(value)))
+            Token::Symbol(name) => Ok(self.record(Value::Symbol(Symbol::intern(&name)), span)),
+            Token::Number(number) => Ok(self.record(Value::Number(number), span)),
+            Token::String(string) => Ok(self.record(Value::String(string), span)),
+            Token::Bool(value) => Ok(self.record(Value::Bool(value), span)),
+        }
+    }
+
+    fn parse_program(&mut self) -> Result<Vec<Arc<Value>>, Error> {
+        let mut values = Vec::new();
+        loop {
+            self.skip_datum_comments()?;
+            if self.tokenizer.peek()?.is_none() {
+                break;
             }
-            Token::Symbol(name) => Ok(Arc::new(Value::Symbol(name))),
+            values.push(self.parse_value("an expression")?.0);
         }
-    } else {
-        Err(Error::ParseError)
+        Ok(values)
     }
 }
 
+// The file name used in spans for input that does not come from a file.
+const INPUT_NAME: &str = "<input>";
+
+// Parses exactly one expression; anything after it is an error.
+#[cfg(test)]
 pub fn parse(input: &str) -> Result<Arc<Value>, Error> {
-    let mut tokenizer = Tokenizer::new(input);
-    let value = parse_value(&mut tokenizer)?;
-    if tokenizer.next().is_some() {
-        return Err(Error::ParseError);
+    let mut parser = Parser::new(INPUT_NAME, input);
+    let (value, _) = parser.parse_value("an expression")?;
+    parser.skip_datum_comments()?;
+    if let Some((token, span)) = parser.tokenizer.next()? {
+        return Err(Error::from(ParseError {
+            message: format!(
+                "unexpected {} after the end of the expression",
+                token.describe()
+            ),
+            span,
+        }));
     }
-    return Ok(value);
+    Ok(value)
+}
+
+// Parses any number of top-level expressions, such as the contents of a file.
+pub fn parse_program(input: &str) -> Result<Vec<Arc<Value>>, Error> {
+    Parser::new(INPUT_NAME, input).parse_program()
+}
+
+// Like parse_program, but also returns where each parsed value came from.
+// `file` names the input in spans and errors.
+pub fn parse_program_with_source_map(
+    file: &str,
+    input: &str,
+) -> Result<(Vec<Arc<Value>>, SourceMap), Error> {
+    let mut parser = Parser::new(file, input);
+    let values = parser.parse_program()?;
+    Ok((values, parser.source_map))
 }
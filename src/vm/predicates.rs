@@ -0,0 +1,118 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::vm::values::*;
+use crate::vm::Error;
+
+// Identity, except that nil, booleans and symbols are compared by value, since
+// the reader makes a new one each time it reads them.
+fn is_eq(lhs: &Arc<Value>, rhs: &Arc<Value>) -> bool {
+    Arc::ptr_eq(lhs, rhs)
+        || match (lhs.deref(), rhs.deref()) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(lhs), Value::Bool(rhs)) => lhs == rhs,
+            (Value::Symbol(lhs), Value::Symbol(rhs)) => lhs == rhs,
+            _ => false,
+        }
+}
+
+// Like eq?, but numbers are also compared by value. Numbers of different
+// exactness are never eqv?, so (eqv? 2 2.0) is false even though (= 2 2.0).
+pub fn is_eqv(lhs: &Arc<Value>, rhs: &Arc<Value>) -> bool {
+    is_eq(lhs, rhs)
+        || matches!(
+            (lhs.deref(), rhs.deref()),
+            (Value::Number(lhs), Value::Number(rhs)) if lhs == rhs
+        )
+}
+
+// Walks the list two cells at a time, with a second walker going one cell at
+// a time, so that a circular list is caught when the two meet.
+fn is_proper_list(value: &Arc<Value>) -> bool {
+    fn next(value: &Arc<Value>) -> Option<Arc<Value>> {
+        match value.deref() {
+            Value::Cell(cell) => Some(cell.right()),
+            _ => None,
+        }
+    }
+
+    let mut slow = value.clone();
+    let mut fast = value.clone();
+    loop {
+        for _ in 0..2 {
+            match fast.deref() {
+                Value::Nil => return true,
+                Value::Cell(cell) => fast = cell.right(),
+                _ => return false,
+            }
+        }
+        slow = next(&slow).unwrap();
+        if Arc::ptr_eq(&slow, &fast) {
+            return false;
+        }
+    }
+}
+
+pub fn eq(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Ok(Value::from_bool(is_eq(&args[0], &args[1])))
+}
+
+pub fn eqv(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Ok(Value::from_bool(is_eqv(&args[0], &args[1])))
+}
+
+// Structural equality, as in the PartialEq of Value: lists and strings are
+// equal if their contents are, while procedures are only equal to themselves.
+// Circular lists are equal if unrolling them gives equal lists, so this
+// terminates on them too.
+pub fn equal(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Ok(Value::from_bool(args[0] == args[1]))
+}
+
+pub fn not(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Ok(Value::from_bool(!args[0].is_truthy()))
+}
+
+pub fn is_null(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Ok(Value::from_bool(matches!(args[0].deref(), Value::Nil)))
+}
+
+pub fn is_pair(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Ok(Value::from_bool(matches!(args[0].deref(), Value::Cell(_))))
+}
+
+pub fn is_list(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Ok(Value::from_bool(is_proper_list(&args[0])))
+}
+
+pub fn is_symbol(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Ok(Value::from_bool(matches!(
+        args[0].deref(),
+        Value::Symbol(_)
+    )))
+}
+
+pub fn is_number(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Ok(Value::from_bool(matches!(
+        args[0].deref(),
+        Value::Number(_)
+    )))
+}
+
+pub fn is_string(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Ok(Value::from_bool(matches!(
+        args[0].deref(),
+        Value::String(_)
+    )))
+}
+
+pub fn is_boolean(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Ok(Value::from_bool(matches!(args[0].deref(), Value::Bool(_))))
+}
+
+pub fn is_procedure(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Ok(Value::from_bool(matches!(
+        args[0].deref(),
+        Value::Function(_)
+    )))
+}
@@ -0,0 +1,541 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::vm::expand::rebuild;
+use crate::vm::values::*;
+use crate::vm::*;
+
+// What the head of a form refers to, as far as can be told before it runs.
+enum Head {
+    // A local variable, a global one that is not a special form, or a form.
+    Call,
+    Macro,
+    // A special form other than a macro, by name.
+    SpecialForm(String),
+    // A global that is not bound yet, and that the form being resolved does
+    // not define, which may still turn out to be a macro.
+    Unbound,
+}
+
+// The names a local scope will bind, in the order it binds them, and which
+// of them are bound to macros. A scope is open when code in it uses
+// a macro, which could define any name in it, so names not found in it cannot
+// be resolved any further out.
+#[derive(Default)]
+struct Frame {
+    names: Vec<Symbol>,
+    macros: Vec<Symbol>,
+    open: bool,
+}
+
+struct Resolver<'a> {
+    scope: &'a Arc<Scope>,
+    // The local scopes around the form being resolved, innermost last.
+    frames: Vec<Frame>,
+    // The globals that the top-level form defines, so that uses of them in
+    // it, such as a recursive call, are known not to be macros before they
+    // are bound.
+    globals: Frame,
+}
+
+// Rewrites the variables in form that refer to local scopes as Local values,
+// which say how many scopes out the variable is and where it is in that
+// scope, so that evaluating one does not search the scope chain by name.
+// This runs on each top-level form after it is macro expanded. Globals, and
+// anything resolve cannot be sure of, are left to be looked up by name.
+pub fn resolve(scope: &Arc<Scope>, form: &Arc<Value>) -> Arc<Value> {
+    let mut resolver = Resolver {
+        scope,
+        frames: Vec::new(),
+        globals: Frame::default(),
+    };
+    resolver.scan(form);
+    resolver.resolve_form(form)
+}
+
+// Turns the local variables in form back into plain symbols. Macros get
+// their arguments this way, since the code they expand to may put them in
+// scopes that resolve never saw.
+pub fn unresolve(form: &Arc<Value>) -> Arc<Value> {
+    match form.deref() {
+        Value::Local(local) => Arc::new(Value::Symbol(local.symbol.clone())),
+        Value::Cell(_) => match form.to_args() {
+            Ok(items) => rebuild(form, &items, items.iter().map(unresolve).collect()),
+            Err(_) => form.clone(),
+        },
+        Value::Quasiquoted(value) => wrap(form, value, unresolve(value), Value::Quasiquoted),
+        Value::Unquoted(value) => wrap(form, value, unresolve(value), Value::Unquoted),
+        Value::UnquoteSpliced(value) => wrap(form, value, unresolve(value), Value::UnquoteSpliced),
+        _ => form.clone(),
+    }
+}
+
+// The value around inner rebuilt around resolved, or the original value if
+// inner did not change.
+fn wrap(
+    form: &Arc<Value>,
+    inner: &Arc<Value>,
+    resolved: Arc<Value>,
+    make: fn(Arc<Value>) -> Value,
+) -> Arc<Value> {
+    if Arc::ptr_eq(inner, &resolved) {
+        form.clone()
+    } else {
+        Arc::new(make(resolved))
+    }
+}
+
+// The names of a ((name init) ...) list, or None if it is malformed.
+fn binding_names(bindings: &Arc<Value>) -> Option<Vec<Symbol>> {
+    bindings
+        .to_args()
+        .ok()?
+        .iter()
+        .map(|binding| match binding.to_args().ok()?.as_slice() {
+            [name, _] => name.identifier(),
+            _ => None,
+        })
+        .collect()
+}
+
+fn binding_inits(bindings: &Arc<Value>) -> Vec<Arc<Value>> {
+    bindings
+        .to_args()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|binding| binding.to_args().ok()?.get(1).cloned())
+        .collect()
+}
+
+impl Resolver<'_> {
+    fn lookup(&self, name: Symbol, original: Symbol) -> Option<Local> {
+        for (depth, frame) in self.frames.iter().rev().enumerate() {
+            if let Some(index) = frame.names.iter().position(|bound| *bound == name) {
+                return Some(Local {
+                    symbol: name,
+                    original,
+                    depth,
+                    index,
+                });
+            }
+            if frame.open {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn head(&self, head: &Arc<Value>) -> Head {
+        let Some(name) = head.identifier() else {
+            return Head::Call;
+        };
+        if let Some(frame) = self
+            .frames
+            .iter()
+            .rev()
+            .find(|frame| frame.names.contains(&name))
+        {
+            if frame.macros.contains(&name) {
+                return Head::Macro;
+            }
+            return Head::Call;
+        }
+        let value = match head.deref() {
+            Value::Alias(alias) => alias.lookup(self.scope),
+            _ => self.scope.lookup(&name),
+        };
+        match value.as_deref() {
+            Ok(Value::SpecialForm(special_form)) if special_form.is_macro() => Head::Macro,
+            Ok(Value::SpecialForm(special_form)) => Head::SpecialForm(special_form.name.clone()),
+            Ok(_) => Head::Call,
+            Err(_) if self.globals.macros.contains(&name) => Head::Macro,
+            Err(_) if self.globals.names.contains(&name) => Head::Call,
+            Err(_) => Head::Unbound,
+        }
+    }
+
+    // Resolves f in a new innermost frame binding names, followed by the
+    // names that the forms of body define.
+    fn enter<T>(
+        &mut self,
+        names: Vec<Symbol>,
+        body: &[Arc<Value>],
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        self.frames.push(Frame::default());
+        for name in names {
+            self.define(Some(name));
+        }
+        for form in body {
+            self.scan(form);
+        }
+        let result = f(self);
+        self.frames.pop();
+        result
+    }
+
+    // The frame that definitions go in: the innermost local scope, or the
+    // globals outside of any.
+    fn innermost(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap_or(&mut self.globals)
+    }
+
+    fn define(&mut self, name: Option<Symbol>) {
+        let Some(name) = name else {
+            return;
+        };
+        let frame = self.innermost();
+        if !frame.names.contains(&name) {
+            frame.names.push(name);
+        }
+    }
+
+    fn define_macro(&mut self, name: Option<Symbol>) {
+        self.define(name.clone());
+        if let Some(name) = name {
+            self.innermost().macros.push(name);
+        }
+    }
+
+    // Whether form makes a macro, as the value in (define name (macro ...))
+    // does.
+    fn is_transformer(&self, form: &Arc<Value>) -> bool {
+        let Some(head) = form.to_args().ok().and_then(|items| items.first().cloned()) else {
+            return false;
+        };
+        matches!(self.head(&head), Head::SpecialForm(name) if name == "macro" || name == "syntax-rules")
+    }
+
+    // Adds the names that evaluating form defines in the innermost frame.
+    // Forms that make a scope of their own are skipped, since what they
+    // define goes in that scope instead.
+    fn scan(&mut self, form: &Arc<Value>) {
+        let Ok(items) = form.to_args() else {
+            return;
+        };
+        let Some(head) = items.first() else {
+            return;
+        };
+        let name = match self.head(head) {
+            Head::Macro | Head::Unbound => {
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.open = true;
+                }
+                return;
+            }
+            Head::Call => return self.scan_all(&items),
+            Head::SpecialForm(name) => name,
+        };
+        match name.as_str() {
+            "quote" | "lambda" | "macro" | "syntax-rules" | "letrec" | "letrec*" => {}
+            "define-syntax" => self.define_macro(items.get(1).and_then(|name| name.identifier())),
+            "define" => match items.get(1).map(|target| target.deref()) {
+                Some(Value::Cell(cell)) => self.define(cell.left().identifier()),
+                Some(target) if items.get(2).is_some_and(|value| self.is_transformer(value)) => {
+                    self.define_macro(target.identifier())
+                }
+                Some(target) => {
+                    self.define(target.identifier());
+                    self.scan_all(&items[2..]);
+                }
+                None => {}
+            },
+            "let" => match items.get(1) {
+                Some(name) if name.identifier().is_some() => {
+                    if let Some(bindings) = items.get(2) {
+                        self.scan_all(&binding_inits(bindings));
+                    }
+                }
+                Some(bindings) => self.scan_all(&binding_inits(bindings)),
+                None => {}
+            },
+            // Only the first init is evaluated in this scope, and the body
+            // too if there are no bindings.
+            "let*" => match items.get(1).map(binding_inits) {
+                Some(inits) if inits.is_empty() => self.scan_all(&items[2..]),
+                Some(inits) => self.scan(&inits[0]),
+                None => {}
+            },
+            // The clauses are evaluated in a scope of their own.
+            "guard" => self.scan_all(items.get(2..).unwrap_or(&[])),
+            "cond" => {
+                for clause in &items[1..] {
+                    self.scan_all(&clause.to_args().unwrap_or_default());
+                }
+            }
+            "case" => {
+                self.scan_all(&items[1..items.len().min(2)]);
+                for clause in items.iter().skip(2) {
+                    self.scan_all(clause.to_args().unwrap_or_default().get(1..).unwrap_or(&[]));
+                }
+            }
+            _ => self.scan_all(&items[1..]),
+        }
+    }
+
+    fn scan_all(&mut self, forms: &[Arc<Value>]) {
+        for form in forms {
+            self.scan(form);
+        }
+    }
+
+    fn resolve_form(&mut self, form: &Arc<Value>) -> Arc<Value> {
+        let (name, original) = match form.deref() {
+            Value::Symbol(name) => (name.clone(), name.clone()),
+            Value::Alias(alias) => (alias.name.clone(), alias.original.clone()),
+            Value::Quasiquoted(template) => {
+                let resolved = self.resolve_template(template, 1);
+                return wrap(form, template, resolved, Value::Quasiquoted);
+            }
+            Value::Cell(_) => return self.resolve_list(form),
+            _ => return form.clone(),
+        };
+        match self.lookup(name, original) {
+            Some(local) => Arc::new(Value::Local(local)),
+            None => form.clone(),
+        }
+    }
+
+    fn resolve_all(&mut self, forms: &[Arc<Value>]) -> Vec<Arc<Value>> {
+        forms.iter().map(|form| self.resolve_form(form)).collect()
+    }
+
+    // Resolves the unquoted parts of a quasiquote template, where depth
+    // counts the quasiquotes around it as in eval.
+    fn resolve_template(&mut self, template: &Arc<Value>, depth: usize) -> Arc<Value> {
+        match template.deref() {
+            Value::Unquoted(value) if depth == 1 => {
+                let resolved = self.resolve_form(value);
+                wrap(template, value, resolved, Value::Unquoted)
+            }
+            Value::UnquoteSpliced(value) if depth == 1 => {
+                let resolved = self.resolve_form(value);
+                wrap(template, value, resolved, Value::UnquoteSpliced)
+            }
+            Value::Unquoted(value) => {
+                let resolved = self.resolve_template(value, depth - 1);
+                wrap(template, value, resolved, Value::Unquoted)
+            }
+            Value::UnquoteSpliced(value) => {
+                let resolved = self.resolve_template(value, depth - 1);
+                wrap(template, value, resolved, Value::UnquoteSpliced)
+            }
+            Value::Quasiquoted(value) => {
+                let resolved = self.resolve_template(value, depth + 1);
+                wrap(template, value, resolved, Value::Quasiquoted)
+            }
+            Value::Quoted(value) => {
+                let resolved = self.resolve_template(value, depth);
+                wrap(template, value, resolved, Value::Quoted)
+            }
+            Value::Cell(_) => match template.to_args() {
+                Ok(items) => {
+                    let resolved = items
+                        .iter()
+                        .map(|item| self.resolve_template(item, depth))
+                        .collect();
+                    rebuild(template, &items, resolved)
+                }
+                Err(_) => template.clone(),
+            },
+            _ => template.clone(),
+        }
+    }
+
+    // Malformed forms are left as they are, for eval to report.
+    fn resolve_list(&mut self, form: &Arc<Value>) -> Arc<Value> {
+        let Ok(items) = form.to_args() else {
+            return form.clone();
+        };
+        let Some(head) = items.first() else {
+            return form.clone();
+        };
+        let resolved = match self.head(head) {
+            Head::Macro => return form.clone(),
+            Head::Call | Head::Unbound => self.resolve_all(&items),
+            Head::SpecialForm(name) => match name.as_str() {
+                "quote" | "macro" | "syntax-rules" => return form.clone(),
+                "lambda" => self.resolve_function(&items, items.get(1).cloned()),
+                "define" => match items.get(1).map(|target| target.deref()) {
+                    Some(Value::Cell(cell)) => self.resolve_function(&items, Some(cell.right())),
+                    _ => self.resolve_after(&items, 2),
+                },
+                "define-syntax" => self.resolve_after(&items, 2),
+                "let" => match items.get(1) {
+                    Some(name) if name.identifier().is_some() => self.resolve_named_let(&items),
+                    _ => self.resolve_let(&items),
+                },
+                "let*" => self.resolve_let_star(&items),
+                "letrec" | "letrec*" => self.resolve_letrec(&items),
+                "guard" => self.resolve_guard(&items),
+                "cond" => {
+                    let mut resolved = vec![items[0].clone()];
+                    resolved.extend(
+                        items[1..]
+                            .iter()
+                            .map(|clause| self.resolve_clause(clause, 0)),
+                    );
+                    resolved
+                }
+                "case" => {
+                    let mut resolved = self.resolve_all(&items[..items.len().min(2)]);
+                    resolved.extend(
+                        items
+                            .iter()
+                            .skip(2)
+                            .map(|clause| self.resolve_clause(clause, 1)),
+                    );
+                    resolved
+                }
+                _ => self.resolve_all(&items),
+            },
+        };
+        rebuild(form, &items, resolved)
+    }
+
+    // The items of form with those from start on resolved.
+    fn resolve_after(&mut self, items: &[Arc<Value>], start: usize) -> Vec<Arc<Value>> {
+        let start = start.min(items.len());
+        let mut resolved = items[..start].to_vec();
+        resolved.extend(self.resolve_all(&items[start..]));
+        resolved
+    }
+
+    // A cond or case clause, keeping the first skip items as they are.
+    fn resolve_clause(&mut self, clause: &Arc<Value>, skip: usize) -> Arc<Value> {
+        match clause.to_args() {
+            Ok(items) => {
+                let resolved = self.resolve_after(&items, skip);
+                rebuild(clause, &items, resolved)
+            }
+            Err(_) => clause.clone(),
+        }
+    }
+
+    // (lambda formals body...), or (define (name formals...) body...), where
+    // formals is given separately.
+    fn resolve_function(
+        &mut self,
+        items: &[Arc<Value>],
+        formals: Option<Arc<Value>>,
+    ) -> Vec<Arc<Value>> {
+        let Some(Ok(formals)) = formals.map(|formals| Formals::parse(&formals)) else {
+            return items.to_vec();
+        };
+        let body = &items[2..];
+        let mut resolved = items[..2].to_vec();
+        resolved.extend(self.enter(formals.names(), body, |resolver| resolver.resolve_all(body)));
+        resolved
+    }
+
+    fn resolve_bindings(&mut self, bindings: &Arc<Value>) -> Arc<Value> {
+        let Ok(items) = bindings.to_args() else {
+            return bindings.clone();
+        };
+        let resolved = items
+            .iter()
+            .map(|binding| self.resolve_clause(binding, 1))
+            .collect();
+        rebuild(bindings, &items, resolved)
+    }
+
+    // (let ((name init) ...) body...), with the inits resolved outside the
+    // new scope.
+    fn resolve_let(&mut self, items: &[Arc<Value>]) -> Vec<Arc<Value>> {
+        let Some(names) = items.get(1).and_then(binding_names) else {
+            return items.to_vec();
+        };
+        let body = &items[2..];
+        let mut resolved = vec![items[0].clone(), self.resolve_bindings(&items[1])];
+        resolved.extend(self.enter(names, body, |resolver| resolver.resolve_all(body)));
+        resolved
+    }
+
+    // (let loop ((name init) ...) body...) binds loop in a scope of its own,
+    // which the scope of the names is a child of.
+    fn resolve_named_let(&mut self, items: &[Arc<Value>]) -> Vec<Arc<Value>> {
+        let (Some(name), Some(names)) =
+            (items[1].identifier(), items.get(2).and_then(binding_names))
+        else {
+            return items.to_vec();
+        };
+        let body = &items[3..];
+        let mut resolved = vec![
+            items[0].clone(),
+            items[1].clone(),
+            self.resolve_bindings(&items[2]),
+        ];
+        resolved.extend(self.enter(vec![name], &[], |resolver| {
+            resolver.enter(names, body, |resolver| resolver.resolve_all(body))
+        }));
+        resolved
+    }
+
+    // let* makes a scope for each binding, and the body goes in the last.
+    fn resolve_let_star(&mut self, items: &[Arc<Value>]) -> Vec<Arc<Value>> {
+        let (Some(names), Some(Ok(bindings))) = (
+            items.get(1).and_then(binding_names),
+            items.get(1).map(|bindings| bindings.to_args()),
+        ) else {
+            return items.to_vec();
+        };
+        let depth = self.frames.len();
+        let mut resolved_bindings = Vec::new();
+        for (binding, name) in bindings.iter().zip(names) {
+            resolved_bindings.push(self.resolve_clause(binding, 1));
+            self.frames.push(Frame::default());
+            self.define(Some(name));
+        }
+        let body = &items[2..];
+        if self.frames.len() > depth {
+            self.scan_all(body);
+        }
+        let mut resolved = vec![
+            items[0].clone(),
+            rebuild(&items[1], &bindings, resolved_bindings),
+        ];
+        resolved.extend(self.resolve_all(body));
+        self.frames.truncate(depth);
+        resolved
+    }
+
+    // letrec evaluates its inits in the new scope as well as its body.
+    fn resolve_letrec(&mut self, items: &[Arc<Value>]) -> Vec<Arc<Value>> {
+        let Some(names) = items.get(1).and_then(binding_names) else {
+            return items.to_vec();
+        };
+        let mut forms = binding_inits(&items[1]);
+        forms.extend_from_slice(&items[2..]);
+        self.enter(names, &forms, |resolver| {
+            let mut resolved = vec![items[0].clone(), resolver.resolve_bindings(&items[1])];
+            resolved.extend(resolver.resolve_all(&items[2..]));
+            resolved
+        })
+    }
+
+    // (guard (var clause...) body...) evaluates the body where it is, and
+    // the clauses in a new scope binding var.
+    fn resolve_guard(&mut self, items: &[Arc<Value>]) -> Vec<Arc<Value>> {
+        let Some(Ok(spec)) = items.get(1).map(|spec| spec.to_args()) else {
+            return items.to_vec();
+        };
+        let Some(name) = spec.first().and_then(|name| name.identifier()) else {
+            return items.to_vec();
+        };
+        let clauses = &spec[1..];
+        let forms: Vec<Arc<Value>> = clauses
+            .iter()
+            .flat_map(|clause| clause.to_args().unwrap_or_default())
+            .collect();
+        let mut resolved_spec = vec![spec[0].clone()];
+        resolved_spec.extend(self.enter(vec![name], &forms, |resolver| {
+            clauses
+                .iter()
+                .map(|clause| resolver.resolve_clause(clause, 0))
+                .collect::<Vec<_>>()
+        }));
+        let mut resolved = vec![items[0].clone(), rebuild(&items[1], &spec, resolved_spec)];
+        resolved.extend(self.resolve_all(&items[2..]));
+        resolved
+    }
+}
@@ -0,0 +1,97 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::vm::values::*;
+use crate::vm::{Arity, Error, Number};
+
+pub fn as_str(value: &Arc<Value>) -> Result<&str, Error> {
+    match value.deref() {
+        Value::String(string) => Ok(string),
+        _ => Err(Error::type_error("a string", value)),
+    }
+}
+
+fn as_index(value: &Arc<Value>) -> Result<usize, Error> {
+    match value.deref() {
+        Value::Number(Number::Fixnum(index)) => {
+            usize::try_from(*index).map_err(|_| Error::eval_error("Negative index".to_string()))
+        }
+        _ => Err(Error::type_error("an index", value)),
+    }
+}
+
+pub fn from_string(string: String) -> Arc<Value> {
+    Arc::new(Value::String(string))
+}
+
+pub fn string_length(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    let length = as_str(&args[0])?.chars().count();
+    Ok(Arc::new(Value::Number(Number::Fixnum(length as i64))))
+}
+
+// (substring string start [end]), where indices count characters, not bytes.
+pub fn substring(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Arity::Between(2, 3).check(args.len())?;
+    let string = as_str(&args[0])?;
+    let length = string.chars().count();
+    let start = as_index(&args[1])?;
+    let end = match args.get(2) {
+        Some(end) => as_index(end)?,
+        None => length,
+    };
+    if start > end || end > length {
+        return Err(Error::eval_error(format!(
+            "Substring range {}..{} out of bounds for length {}",
+            start, end, length
+        )));
+    }
+    Ok(from_string(
+        string.chars().skip(start).take(end - start).collect(),
+    ))
+}
+
+pub fn string_append(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    let mut result = String::new();
+    for arg in args {
+        result.push_str(as_str(arg)?);
+    }
+    Ok(from_string(result))
+}
+
+// (string-split string [separator]) splits on whitespace when no separator is
+// given, dropping empty pieces.
+pub fn string_split(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    let pieces: Vec<&str> = match args {
+        [string] => as_str(string)?.split_whitespace().collect(),
+        [string, separator] => {
+            let string = as_str(string)?;
+            let separator = as_str(separator)?;
+            if separator.is_empty() {
+                return Err(Error::eval_error("Empty separator".to_string()));
+            }
+            string.split(separator).collect()
+        }
+        _ => return Err(Error::arity_error(Arity::Between(1, 2), args.len())),
+    };
+    Ok(Cell::from_vec(
+        pieces
+            .into_iter()
+            .map(|piece| from_string(piece.to_string()))
+            .collect(),
+    ))
+}
+
+// (string-join list [separator]) joins with a single space by default.
+pub fn string_join(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    let separator = match args {
+        [_] => " ",
+        [_, separator] => as_str(separator)?,
+        _ => return Err(Error::arity_error(Arity::Between(1, 2), args.len())),
+    };
+    let pieces = args[0]
+        .to_args()?
+        .iter()
+        .map(|piece| as_str(piece).map(str::to_string))
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(from_string(pieces.join(separator)))
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, RwLock};
+
+use crate::vm::strings::{as_str, from_string};
+use crate::vm::values::*;
+use crate::vm::{Arity, Error};
+
+// Uninterned symbols are printed with this in front of their name. The reader
+// and string->symbol refuse names that start with it, so that what is printed
+// for an uninterned symbol never reads back as an interned one.
+pub const UNINTERNED_PREFIX: &str = "#:";
+
+// An interned symbol is an index into a global table of names, so that
+// comparing and hashing one costs no more than for an integer, and interning
+// the same name always gives the same symbol. An uninterned symbol, as made by
+// gensym, owns its name instead and is equal only to its own copies, so that
+// it is freed along with its last reference.
+#[derive(Clone)]
+pub struct Symbol(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Interned(u32),
+    Uninterned(Arc<str>),
+}
+
+#[derive(Default)]
+struct SymbolTable {
+    names: Vec<Arc<str>>,
+    ids: HashMap<Arc<str>, u32>,
+}
+
+static SYMBOLS: LazyLock<RwLock<SymbolTable>> = LazyLock::new(Default::default);
+
+// Names that special forms and syntax-rules look for among their arguments,
+// interned once so that checking for one only compares ids.
+pub static ELSE: LazyLock<Symbol> = LazyLock::new(|| Symbol::intern("else"));
+pub static ARROW: LazyLock<Symbol> = LazyLock::new(|| Symbol::intern("=>"));
+pub static QUOTE: LazyLock<Symbol> = LazyLock::new(|| Symbol::intern("quote"));
+pub static ELLIPSIS: LazyLock<Symbol> = LazyLock::new(|| Symbol::intern("..."));
+pub static WILDCARD: LazyLock<Symbol> = LazyLock::new(|| Symbol::intern("_"));
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        if let Some(id) = SYMBOLS.read().unwrap().ids.get(name) {
+            return Symbol(Repr::Interned(*id));
+        }
+        let mut table = SYMBOLS.write().unwrap();
+        // Another thread may have interned the name since the read lock was
+        // released.
+        if let Some(id) = table.ids.get(name) {
+            return Symbol(Repr::Interned(*id));
+        }
+        let id = u32::try_from(table.names.len()).expect("too many symbols");
+        let name: Arc<str> = Arc::from(name);
+        table.names.push(name.clone());
+        table.ids.insert(name, id);
+        Symbol(Repr::Interned(id))
+    }
+
+    pub fn uninterned(name: &str) -> Symbol {
+        Symbol(Repr::Uninterned(Arc::from(name)))
+    }
+
+    pub fn name(&self) -> Arc<str> {
+        match &self.0 {
+            Repr::Interned(id) => SYMBOLS.read().unwrap().names[*id as usize].clone(),
+            Repr::Uninterned(name) => name.clone(),
+        }
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        match (&self.0, &other.0) {
+            (Repr::Interned(id), Repr::Interned(other)) => id == other,
+            (Repr::Uninterned(name), Repr::Uninterned(other)) => Arc::ptr_eq(name, other),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match &self.0 {
+            Repr::Interned(id) => id.hash(state),
+            Repr::Uninterned(name) => Arc::as_ptr(name).cast::<u8>().hash(state),
+        }
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Repr::Interned(id) => write!(f, "{}", SYMBOLS.read().unwrap().names[*id as usize]),
+            Repr::Uninterned(name) => write!(f, "{}{}", UNINTERNED_PREFIX, name),
+        }
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+pub fn string_to_symbol(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    let name = as_str(&args[0])?;
+    if name.starts_with(UNINTERNED_PREFIX) {
+        return Err(Error::eval_error(format!(
+            "Cannot make an uninterned symbol by name: {}",
+            name
+        )));
+    }
+    Ok(Arc::new(Value::Symbol(Symbol::intern(name))))
+}
+
+// The name of an uninterned symbol is given without its #: prefix.
+pub fn symbol_to_string(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    match args[0].deref() {
+        Value::Symbol(symbol) => Ok(from_string(symbol.name().to_string())),
+        _ => Err(Error::type_error("a symbol", &args[0])),
+    }
+}
+
+static NEXT_GENSYM: AtomicUsize = AtomicUsize::new(1);
+
+// (gensym [prefix]) makes a fresh uninterned symbol, such as #:g1, for a
+// macro to bind without capturing any of the names at the use site. The
+// prefix, g by default, is a string or a symbol.
+pub fn gensym(args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    Arity::Between(0, 1).check(args.len())?;
+    let prefix = match args.first().map(|prefix| prefix.deref()) {
+        None => "g".into(),
+        Some(Value::String(prefix)) => prefix.as_str().into(),
+        Some(Value::Symbol(prefix)) => prefix.name(),
+        Some(_) => return Err(Error::type_error("a string or a symbol", &args[0])),
+    };
+    let id = NEXT_GENSYM.fetch_add(1, Ordering::Relaxed);
+    Ok(Arc::new(Value::Symbol(Symbol::uninterned(&format!(
+        "{}{}",
+        prefix, id
+    )))))
+}
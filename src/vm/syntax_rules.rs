@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::vm::symbols;
+use crate::vm::values::*;
+use crate::vm::*;
+
+const ELLIPSIS: &str = "...";
+
+// What a pattern variable matched: a single form, or one binding per form
+// matched by the ellipsis that follows it.
+#[derive(Clone, Debug)]
+enum Binding {
+    One(Arc<Value>),
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<Symbol, Binding>;
+
+fn is_ellipsis(value: &Arc<Value>) -> bool {
+    matches!(value.deref(), Value::Symbol(symbol) if *symbol == *symbols::ELLIPSIS)
+}
+
+// A transformer made by (syntax-rules (literal...) (pattern template)...).
+// Each use is rewritten by the template of the first pattern that matches it,
+// and the result is evaluated where the macro was used.
+#[derive(Debug)]
+pub struct SyntaxRules {
+    scope: Arc<Scope>,
+    literals: Vec<Symbol>,
+    rules: Vec<(Arc<Value>, Arc<Value>)>,
+}
+
+impl SyntaxRules {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        scope: Arc<Scope>,
+        literals: Vec<Symbol>,
+        rules: Vec<(Arc<Value>, Arc<Value>)>,
+    ) -> Arc<Value> {
+        SpecialForm::from_body(
+            "#syntax-rules".to_string(),
+            None,
+            SpecialFormBody::SyntaxRules(SyntaxRules {
+                scope,
+                literals,
+                rules,
+            }),
+        )
+    }
+
+    pub fn expand(&self, args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+        for (pattern, template) in &self.rules {
+            // The keyword position of the pattern is ignored, as it is always
+            // the macro itself.
+            let patterns = pattern.to_args()?;
+            let mut bindings = Bindings::new();
+            if self.match_list(&patterns[1..], args, &mut bindings) {
+                let mut expander = Expander {
+                    scope: &self.scope,
+                    renames: HashMap::new(),
+                };
+                return expander.expand(template, &bindings, false);
+            }
+        }
+        Err(Error::eval_error(format!(
+            "No syntax-rules pattern matches {}",
+            to_string(&Cell::from_vec(args.to_vec()))
+        )))
+    }
+
+    fn match_pattern(
+        &self,
+        pattern: &Arc<Value>,
+        form: &Arc<Value>,
+        bindings: &mut Bindings,
+    ) -> bool {
+        match pattern.deref() {
+            Value::Symbol(name) if *name == *symbols::WILDCARD => true,
+            Value::Symbol(name) if self.literals.contains(name) => {
+                form.keyword().as_ref() == Some(name)
+            }
+            Value::Symbol(name) => {
+                bindings.insert(name.clone(), Binding::One(form.clone()));
+                true
+            }
+            Value::Cell(_) => match (pattern.to_args(), form.to_args()) {
+                (Ok(patterns), Ok(forms)) => self.match_list(&patterns, &forms, bindings),
+                _ => false,
+            },
+            _ => pattern == form,
+        }
+    }
+
+    // Matches a list of patterns, where a pattern followed by an ellipsis
+    // matches as many forms as the patterns after it leave over.
+    fn match_list(
+        &self,
+        patterns: &[Arc<Value>],
+        forms: &[Arc<Value>],
+        bindings: &mut Bindings,
+    ) -> bool {
+        let Some(position) = patterns.iter().position(is_ellipsis) else {
+            return patterns.len() == forms.len()
+                && patterns
+                    .iter()
+                    .zip(forms)
+                    .all(|(pattern, form)| self.match_pattern(pattern, form, bindings));
+        };
+        let Some(repeated) = position.checked_sub(1).map(|index| &patterns[index]) else {
+            return false;
+        };
+        let (head, tail) = (&patterns[..position - 1], &patterns[position + 1..]);
+        let Some(count) = forms.len().checked_sub(head.len() + tail.len()) else {
+            return false;
+        };
+        if !self.match_list(head, &forms[..head.len()], bindings) {
+            return false;
+        }
+        let mut matches = Vec::new();
+        for form in &forms[head.len()..head.len() + count] {
+            let mut inner = Bindings::new();
+            if !self.match_pattern(repeated, form, &mut inner) {
+                return false;
+            }
+            matches.push(inner);
+        }
+        for name in self.pattern_variables(repeated) {
+            let many = matches
+                .iter_mut()
+                .filter_map(|inner| inner.remove(&name))
+                .collect();
+            bindings.insert(name, Binding::Many(many));
+        }
+        self.match_list(tail, &forms[head.len() + count..], bindings)
+    }
+
+    fn pattern_variables(&self, pattern: &Arc<Value>) -> Vec<Symbol> {
+        match pattern.deref() {
+            Value::Symbol(name)
+                if *name != *symbols::WILDCARD
+                    && !is_ellipsis(pattern)
+                    && !self.literals.contains(name) =>
+            {
+                vec![name.clone()]
+            }
+            Value::Cell(_) => pattern
+                .to_args()
+                .unwrap_or_default()
+                .iter()
+                .flat_map(|pattern| self.pattern_variables(pattern))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// Instantiates a template for one use of a macro.
+struct Expander<'a> {
+    scope: &'a Arc<Scope>,
+    // The fresh, uninterned name for each identifier the template
+    // introduces, so that every occurrence of it in one expansion refers to
+    // the same binding and nothing outside the expansion can name it.
+    renames: HashMap<Symbol, Symbol>,
+}
+
+impl Expander<'_> {
+    // Quoted parts of the template are data, so pattern variables are still
+    // substituted but other symbols are not renamed.
+    fn expand(
+        &mut self,
+        template: &Arc<Value>,
+        bindings: &Bindings,
+        quoted: bool,
+    ) -> Result<Arc<Value>, Error> {
+        match template.deref() {
+            Value::Symbol(name) => match bindings.get(name) {
+                Some(Binding::One(value)) => Ok(value.clone()),
+                Some(Binding::Many(_)) => Err(Error::eval_error(format!(
+                    "Pattern variable used without an ellipsis in template: {}",
+                    name
+                ))),
+                None if quoted => Ok(template.clone()),
+                None => Ok(self.rename(name.clone())),
+            },
+            Value::Cell(_) => {
+                let items = template.to_args()?;
+                match items.as_slice() {
+                    // (... template) escapes the ellipses inside it, so that
+                    // (... ...) gives a literal ellipsis.
+                    [escape, escaped] if is_ellipsis(escape) => Ok(escaped.clone()),
+                    [head, ..]
+                        if head.is_keyword(&symbols::QUOTE)
+                            && !bindings.contains_key(&*symbols::QUOTE) =>
+                    {
+                        let head = self.expand(head, bindings, quoted)?;
+                        let mut expanded = self.expand_list(&items[1..], bindings, true)?;
+                        expanded.insert(0, head);
+                        Ok(Cell::from_vec(expanded))
+                    }
+                    _ => Ok(Cell::from_vec(self.expand_list(&items, bindings, quoted)?)),
+                }
+            }
+            Value::Quoted(value) => {
+                Ok(Arc::new(Value::Quoted(self.expand(value, bindings, true)?)))
+            }
+            Value::Quasiquoted(value) => Ok(Arc::new(Value::Quasiquoted(
+                self.expand(value, bindings, true)?,
+            ))),
+            Value::Unquoted(value) => Ok(Arc::new(Value::Unquoted(
+                self.expand(value, bindings, false)?,
+            ))),
+            Value::UnquoteSpliced(value) => Ok(Arc::new(Value::UnquoteSpliced(
+                self.expand(value, bindings, false)?,
+            ))),
+            _ => Ok(template.clone()),
+        }
+    }
+
+    fn expand_list(
+        &mut self,
+        items: &[Arc<Value>],
+        bindings: &Bindings,
+        quoted: bool,
+    ) -> Result<Vec<Arc<Value>>, Error> {
+        let mut expanded = Vec::new();
+        let mut iter = items.iter().peekable();
+        while let Some(item) = iter.next() {
+            let mut depth = 0;
+            while iter.next_if(|next| is_ellipsis(next)).is_some() {
+                depth += 1;
+            }
+            self.expand_repeated(item, bindings, depth, quoted, &mut expanded)?;
+        }
+        Ok(expanded)
+    }
+
+    // Expands a template followed by depth ellipses, so that x ... ... gives
+    // the forms matched by x at two levels of nesting as one flat sequence.
+    fn expand_repeated(
+        &mut self,
+        template: &Arc<Value>,
+        bindings: &Bindings,
+        depth: usize,
+        quoted: bool,
+        expanded: &mut Vec<Arc<Value>>,
+    ) -> Result<(), Error> {
+        if depth == 0 {
+            expanded.push(self.expand(template, bindings, quoted)?);
+            return Ok(());
+        }
+        for bindings in self.iterations(template, bindings)? {
+            self.expand_repeated(template, &bindings, depth - 1, quoted, expanded)?;
+        }
+        Ok(())
+    }
+
+    // The bindings for each repetition of a template followed by an ellipsis,
+    // which steps through the sequences matched by its pattern variables.
+    fn iterations(
+        &self,
+        template: &Arc<Value>,
+        bindings: &Bindings,
+    ) -> Result<Vec<Bindings>, Error> {
+        let mut names = Vec::new();
+        template_symbols(template, &mut names);
+        let sequences: Vec<(&Symbol, &Vec<Binding>)> = names
+            .iter()
+            .filter_map(|name| match bindings.get(name) {
+                Some(Binding::Many(many)) => Some((name, many)),
+                _ => None,
+            })
+            .collect();
+        let Some(count) = sequences.first().map(|(_, many)| many.len()) else {
+            return Err(Error::eval_error(format!(
+                "No pattern variable to repeat in template: {}",
+                to_string(template)
+            )));
+        };
+        if sequences.iter().any(|(_, many)| many.len() != count) {
+            return Err(Error::eval_error(format!(
+                "Pattern variables repeat a different number of times in template: {}",
+                to_string(template)
+            )));
+        }
+        Ok((0..count)
+            .map(|index| {
+                let mut inner = bindings.clone();
+                for (name, many) in &sequences {
+                    inner.insert((*name).clone(), many[index].clone());
+                }
+                inner
+            })
+            .collect())
+    }
+
+    fn rename(&mut self, symbol: Symbol) -> Arc<Value> {
+        // A rest parameter such as ...args is given the same fresh name as
+        // args itself, and Formals tells it apart by its original name.
+        let name = symbol.name();
+        let base = match name.strip_prefix(ELLIPSIS) {
+            Some(base) if !base.is_empty() => Symbol::intern(base),
+            _ => symbol.clone(),
+        };
+        let fresh = self
+            .renames
+            .entry(base.clone())
+            .or_insert_with(|| Symbol::uninterned(&base.name()))
+            .clone();
+        Arc::new(Value::Alias(Alias {
+            name: fresh,
+            original: symbol,
+            scope: self.scope.clone(),
+        }))
+    }
+}
+
+fn template_symbols(template: &Arc<Value>, names: &mut Vec<Symbol>) {
+    match template.deref() {
+        Value::Symbol(name) => names.push(name.clone()),
+        Value::Cell(_) => {
+            for item in template.to_args().unwrap_or_default() {
+                template_symbols(&item, names);
+            }
+        }
+        Value::Quoted(value)
+        | Value::Quasiquoted(value)
+        | Value::Unquoted(value)
+        | Value::UnquoteSpliced(value) => template_symbols(value, names),
+        _ => {}
+    }
+}
+
+// (syntax-rules (literal...) (pattern template)...) makes a transformer for
+// define-syntax. Identifiers the templates introduce refer to their bindings
+// where syntax-rules was evaluated.
+pub fn syntax_rules(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    Arity::AtLeast(1).check(args.len())?;
+    let literals = args[0]
+        .to_args()?
+        .iter()
+        .map(|literal| match literal.deref() {
+            Value::Symbol(name) => Ok(name.clone()),
+            _ => Err(Error::type_error("a symbol", literal)),
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let rules = args[1..]
+        .iter()
+        .map(|rule| match rule.to_args()?.as_slice() {
+            [pattern, template] if matches!(pattern.deref(), Value::Cell(_)) => {
+                Ok((pattern.clone(), template.clone()))
+            }
+            _ => Err(Error::type_error("a (pattern template) rule", rule)),
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(Trampoline::Return(SyntaxRules::new(
+        scope.clone(),
+        literals,
+        rules,
+    )))
+}
+
+// (define-syntax name transformer) binds name to a macro, such as one made by
+// syntax-rules.
+pub fn define_syntax(scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
+    let name = args[0]
+        .identifier()
+        .ok_or_else(|| Error::type_error("a symbol", &args[0]))?;
+    let transformer = eval(scope, &args[1])?;
+    if !matches!(transformer.deref(), Value::SpecialForm(_)) {
+        return Err(Error::type_error("a syntax transformer", &transformer));
+    }
+    scope.bind(name, transformer);
+    Ok(Trampoline::Return(args[0].clone()))
+}
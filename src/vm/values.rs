@@ -1,36 +1,94 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, Weak};
 
+use crate::vm::resolve;
+use crate::vm::syntax_rules::SyntaxRules;
 use crate::vm::*;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq)]
 pub enum Value {
     Nil,
+    Bool(bool),
     Cell(Cell),
-    Symbol(String),
+    Symbol(Symbol),
+    // An identifier introduced by a syntax-rules template.
+    Alias(Alias),
+    // A variable of a local scope, resolved to where it lives.
+    Local(Local),
+    Number(Number),
+    String(String),
     Quoted(Arc<Value>),
+    // `value, along with the ,value and ,@value forms that may appear inside it.
+    Quasiquoted(Arc<Value>),
+    Unquoted(Arc<Value>),
+    UnquoteSpliced(Arc<Value>),
     Function(Func),
     SpecialForm(SpecialForm),
+    Condition(Condition),
+    // What a letrec variable holds until its init has been evaluated. Reading
+    // a variable that holds it is an error.
+    Unassigned,
 }
 
 impl Value {
     #[cfg(test)]
     pub fn from_name(name: &str) -> Arc<Value> {
-        Arc::new(Value::Symbol(name.to_string()))
+        Arc::new(Value::Symbol(Symbol::intern(name)))
     }
 
     pub fn nil() -> Arc<Value> {
         Arc::new(Value::Nil)
     }
 
+    pub fn unassigned() -> Arc<Value> {
+        Arc::new(Value::Unassigned)
+    }
+
+    pub fn from_bool(value: bool) -> Arc<Value> {
+        Arc::new(Value::Bool(value))
+    }
+
+    // Both #f and the empty list are false, as in traditional Lisps, so that
+    // (if rest ...) still tests for the end of a list. Everything else,
+    // including 0 and "", is true.
+    #[allow(clippy::match_like_matches_macro)]
     pub fn is_truthy(&self) -> bool {
         match self {
-            Value::Nil => false,
+            Value::Nil | Value::Bool(false) => false,
             _ => true,
         }
     }
 
+    // The name an identifier is bound under, or None if it is not one.
+    pub fn identifier(&self) -> Option<Symbol> {
+        match self {
+            Value::Symbol(symbol) => Some(symbol.clone()),
+            Value::Alias(alias) => Some(alias.name.clone()),
+            _ => None,
+        }
+    }
+
+    // The identifier as written in the source, whether or not a macro
+    // introduced it.
+    pub fn keyword(&self) -> Option<Symbol> {
+        match self {
+            Value::Symbol(symbol) => Some(symbol.clone()),
+            Value::Alias(alias) => Some(alias.original.clone()),
+            Value::Local(local) => Some(local.original.clone()),
+            _ => None,
+        }
+    }
+
+    // Whether this is the identifier name as written in the source, such as
+    // else in a cond clause.
+    pub fn is_keyword(&self, keyword: &Symbol) -> bool {
+        self.keyword().as_ref() == Some(keyword)
+    }
+
+    #[allow(clippy::needless_return)]
     pub fn to_args(self: &Arc<Self>) -> Result<Vec<Arc<Value>>, Error> {
         let mut args = Vec::new();
         let mut current = self.clone();
@@ -38,25 +96,46 @@ impl Value {
             match current.deref() {
                 Value::Nil => break,
                 Value::Cell(cell) => {
-                    args.push(cell.left.clone());
-                    current = cell.right.clone();
+                    args.push(cell.left());
+                    current = cell.right();
                 }
-                _ => return Err(Error::TypeError),
+                _ => return Err(Error::type_error("a list", self)),
             }
         }
         return Ok(args);
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+// Both halves of a cell can be replaced after construction by set-car! and
+// set-cdr!, so they sit behind locks like Scope bindings do.
 pub struct Cell {
-    pub left: Arc<Value>,
-    pub right: Arc<Value>,
+    left: RwLock<Arc<Value>>,
+    right: RwLock<Arc<Value>>,
 }
 
 impl Cell {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(left: Arc<Value>, right: Arc<Value>) -> Arc<Value> {
-        Arc::new(Value::Cell(Cell { left, right }))
+        Arc::new(Value::Cell(Cell {
+            left: RwLock::new(left),
+            right: RwLock::new(right),
+        }))
+    }
+
+    pub fn left(&self) -> Arc<Value> {
+        self.left.read().unwrap().clone()
+    }
+
+    pub fn right(&self) -> Arc<Value> {
+        self.right.read().unwrap().clone()
+    }
+
+    pub fn set_left(&self, value: Arc<Value>) {
+        *self.left.write().unwrap() = value;
+    }
+
+    pub fn set_right(&self, value: Arc<Value>) {
+        *self.right.write().unwrap() = value;
     }
 
     // '(), the empty list, is the same as nil, which is the same as None.
@@ -73,56 +152,199 @@ impl Cell {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+// The halves are shown as they print, since printing stops at cycles.
+impl std::fmt::Debug for Cell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.debug_struct("Cell")
+            .field("left", &format_args!("{}", to_string(&self.left())))
+            .field("right", &format_args!("{}", to_string(&self.right())))
+            .finish()
+    }
+}
+
+thread_local! {
+    // The pairs of cells that the calls to Cell::eq on the stack are comparing.
+    static COMPARING: RefCell<HashSet<(usize, usize)>> = RefCell::new(HashSet::new());
+}
+
+// Compares the lists starting at two cells, walking along the cdrs in a loop
+// so that long lists do not use up the stack. A pair of cells met again while
+// it is still being compared is part of a cycle, and has not been found to
+// differ so far, so it is taken to be equal. This way comparing circular
+// lists terminates, however the cycle goes through cars and cdrs.
+impl std::cmp::PartialEq for Cell {
+    fn eq(&self, other: &Self) -> bool {
+        let mut entered = Vec::new();
+        let equal = self.eq_entered(other, &mut entered);
+        COMPARING.with(|comparing| {
+            let mut comparing = comparing.borrow_mut();
+            for key in entered {
+                comparing.remove(&key);
+            }
+        });
+        equal
+    }
+}
+
+impl Cell {
+    fn eq_entered(&self, other: &Cell, entered: &mut Vec<(usize, usize)>) -> bool {
+        // Compares the cars of a pair of cells, giving their cdrs to compare
+        // next, or the answer if it is already known.
+        let mut step = |lhs: &Cell, rhs: &Cell| {
+            let key = (lhs as *const Cell as usize, rhs as *const Cell as usize);
+            if std::ptr::eq(lhs, rhs)
+                || !COMPARING.with(|comparing| comparing.borrow_mut().insert(key))
+            {
+                return Err(true);
+            }
+            entered.push(key);
+            if lhs.left() != rhs.left() {
+                return Err(false);
+            }
+            Ok((lhs.right(), rhs.right()))
+        };
+        let (mut lhs, mut rhs) = match step(self, other) {
+            Ok(next) => next,
+            Err(equal) => return equal,
+        };
+        loop {
+            let next = match (lhs.deref(), rhs.deref()) {
+                (Value::Cell(lhs), Value::Cell(rhs)) => step(lhs, rhs),
+                _ => return lhs == rhs,
+            };
+            (lhs, rhs) = match next {
+                Ok(next) => next,
+                Err(equal) => return equal,
+            };
+        }
+    }
+}
+
+// syntax-rules renames each identifier that a template introduces, so that it
+// cannot capture or be captured by identifiers at the use site. The alias is
+// bound under its fresh name if the expansion binds it, and otherwise refers
+// to the original name in the scope where the macro was defined.
+#[derive(Debug, PartialEq)]
+pub struct Alias {
+    pub name: Symbol,
+    pub original: Symbol,
+    pub scope: Arc<Scope>,
+}
+
+impl Alias {
+    // Errors name the original, since the fresh name never appears in the
+    // source.
+    pub fn lookup(&self, scope: &Scope) -> Result<Arc<Value>, Error> {
+        match scope.lookup(&self.name) {
+            Err(err) if matches!(err.kind, ErrorKind::NotFoundError(_)) => {
+                self.scope.lookup(&self.original)
+            }
+            result => result.map_err(|err| err.with_variable(&self.original)),
+        }
+    }
+
+    pub fn set(&self, scope: &Scope, value: Arc<Value>) -> Result<(), Error> {
+        scope
+            .set(&self.name, value.clone())
+            .or_else(|_| self.scope.set(&self.original, value))
+    }
+}
+
+// A reference to a variable that resolve found in an enclosing local scope:
+// the scope is depth levels out from the one the reference is evaluated in,
+// and the variable is the binding at index in it. The original name is the
+// one written in the source, which differs from symbol for an alias.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Local {
+    pub symbol: Symbol,
+    pub original: Symbol,
+    pub depth: usize,
+    pub index: usize,
+}
+
+// The object handed to a guard clause for an error. Built-in errors are
+// converted to one, and the error procedure raises one made from its
+// arguments. The kind is a symbol naming what went wrong, such as type-error.
+#[derive(Debug, PartialEq)]
+pub struct Condition {
+    pub kind: &'static str,
+    pub message: String,
+    pub irritants: Vec<Arc<Value>>,
+}
+
+impl Condition {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(kind: &'static str, message: String, irritants: Vec<Arc<Value>>) -> Arc<Value> {
+        Arc::new(Value::Condition(Condition {
+            kind,
+            message,
+            irritants,
+        }))
+    }
+
+    // Built-in errors already mention their irritants in the message.
+    pub fn describe(&self) -> String {
+        if self.kind != USER_ERROR {
+            return self.message.clone();
+        }
+        let mut buffer = format!("Error: {}", self.message);
+        for irritant in &self.irritants {
+            buffer.push(' ');
+            buffer.push_str(&to_string(irritant));
+        }
+        buffer
+    }
+}
+
+// The kind of the conditions made by the error procedure.
+pub const USER_ERROR: &str = "error";
+
+#[derive(Debug, PartialEq)]
 pub struct Macro {
     scope: Arc<Scope>,
-    formals: Vec<String>,
-    body: Arc<Value>,
+    formals: Formals,
+    body: Vec<Arc<Value>>,
 }
 
 impl Macro {
-    pub fn new(scope: Arc<Scope>, formals: Vec<String>, body: Arc<Value>) -> Arc<Value> {
-        Arc::new(Value::SpecialForm(SpecialForm {
-            name: "#macro".to_string(),
-            arity: formals.len(),
-            body: SpecialFormBody::Macro(Macro {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(scope: Arc<Scope>, formals: Formals, body: Vec<Arc<Value>>) -> Arc<Value> {
+        SpecialForm::from_body(
+            "#macro".to_string(),
+            None, // Macro does arity checking separately, like lambda.
+            SpecialFormBody::Macro(Macro {
                 scope,
                 formals,
                 body,
             }),
-        }))
+        )
     }
 
-    pub fn call(
-        &self,
-        dynamic_scope: &Arc<Scope>,
-        args: &[Arc<Value>],
-    ) -> Result<Arc<Value>, Error> {
-        let bindings = HashMap::from_iter(
-            self.formals
-                .iter()
-                .zip(args)
-                .map(|(name, value)| (name.clone(), value.clone())),
-        );
-        let lexical_scope = self.scope.new_child(bindings);
-        // We need to evaluate the body in the context of the macro's scope.
-        let code = eval(&lexical_scope, &self.body)?;
-        // We need to evaluate the code produced the macro in the context of where the macro is expanded.
-        eval(dynamic_scope, &code)
+    // The code is produced by evaluating the body in the macro's scope, and
+    // is then evaluated where the macro is used.
+    pub fn expand(&self, args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+        let lexical_scope = self.formals.bind("#macro", &self.scope, args)?;
+        eval_body(&lexical_scope, &self.body)?.resolve()
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq)]
 pub struct Lambda {
     scope: Arc<Scope>,
-    formals: Vec<String>,
-    body: Arc<Value>,
+    formals: Formals,
+    body: Vec<Arc<Value>>,
 }
 
 impl Lambda {
-    pub fn new(scope: Arc<Scope>, formals: Vec<String>, body: Arc<Value>) -> Arc<Value> {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        name: String,
+        scope: Arc<Scope>,
+        formals: Formals,
+        body: Vec<Arc<Value>>,
+    ) -> Arc<Value> {
         Arc::new(Value::Function(Func {
-            name: "#lambda".to_string(),
+            name,
             arity: None, // Lambda does arity checking separately.
             body: FuncBody::Lambda(Lambda {
                 scope,
@@ -132,35 +354,63 @@ impl Lambda {
         }))
     }
 
-    pub fn call(&self, args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
-        const SPLAT_MARKER: &str = "...";
-        let mut args_iter = args.iter();
-        let pairs = self
-            .formals
-            .iter()
-            .map(|name| {
-                if name.starts_with(SPLAT_MARKER) {
-                    let name = name[SPLAT_MARKER.len()..].to_string();
-                    let mut values = Vec::new();
-                    while let Some(arg) = args_iter.next() {
-                        values.push(arg.clone());
-                    }
-                    Ok((name, Cell::from_vec(values)))
-                } else {
-                    let arg = args_iter.next().ok_or(Error::ArityError)?;
-                    Ok((name.clone(), arg.clone()))
-                }
+    // Binds the arguments in a new scope for the body. The name is that of the
+    // function, for reporting arity errors.
+    pub fn bind_args(&self, name: &str, args: &[Arc<Value>]) -> Result<Arc<Scope>, Error> {
+        self.formals.bind(name, &self.scope, args)
+    }
+
+    // Evaluates all but the last expression of the body in a scope made by
+    // bind_args, returning the last as a tail call.
+    pub fn enter(&self, scope: &Arc<Scope>) -> Result<Trampoline, Error> {
+        eval_body(scope, &self.body)
+    }
+}
+
+// An escape-only continuation made by call/cc. Calling it unwinds back to the
+// call/cc that made it, which then returns the value it was called with. Once
+// that call/cc has returned there is nothing left to unwind to, so the
+// continuation can no longer be called.
+pub struct Continuation {
+    pub id: usize,
+    pub active: Arc<AtomicBool>,
+}
+
+impl Continuation {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> (Arc<Value>, usize, Arc<AtomicBool>) {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let active = Arc::new(AtomicBool::new(true));
+        let continuation = Func::new_with_arity(
+            "#continuation".to_string(),
+            FuncBody::Continuation(Continuation {
+                id,
+                active: active.clone(),
+            }),
+            1,
+        );
+        (continuation, id, active)
+    }
+
+    fn escape(&self, value: &Arc<Value>) -> Error {
+        if self.active.load(Ordering::Relaxed) {
+            Error::new(ErrorKind::Escape {
+                id: self.id,
+                value: value.clone(),
             })
-            .collect::<Result<Vec<_>, Error>>()?;
-        let bindings = HashMap::from_iter(pairs.into_iter());
-        let scope = self.scope.new_child(bindings);
-        eval(&scope, &self.body)
+        } else {
+            Error::eval_error("Continuation called after its call/cc returned".to_string())
+        }
     }
 }
 
+pub type NativeFunction = fn(&[Arc<Value>]) -> Result<Arc<Value>, Error>;
+
 pub enum FuncBody {
-    Native(fn(&[Arc<Value>]) -> Result<Arc<Value>, Error>),
+    Native(NativeFunction),
     Lambda(Lambda),
+    Continuation(Continuation),
 }
 
 impl std::fmt::Debug for FuncBody {
@@ -169,22 +419,24 @@ impl std::fmt::Debug for FuncBody {
     }
 }
 
-impl std::cmp::PartialEq for FuncBody {
-    fn eq(&self, _other: &Self) -> bool {
-        false
-    }
-}
-
-impl std::cmp::Eq for FuncBody {}
-
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug)]
 pub struct Func {
     pub name: String,
     pub arity: Option<usize>,
     pub body: FuncBody,
 }
 
+// Procedures are compared by identity, so a function is only equal to itself.
+impl std::cmp::PartialEq for Func {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl std::cmp::Eq for Func {}
+
 impl Func {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(name: String, body: FuncBody) -> Arc<Value> {
         Arc::new(Value::Function(Self {
             name,
@@ -201,47 +453,110 @@ impl Func {
         }))
     }
 
-    pub fn from_native(
-        name: &'static str,
-        native: fn(&[Arc<Value>]) -> Result<Arc<Value>, Error>,
-    ) -> Arc<Value> {
+    pub fn from_native(name: &'static str, native: NativeFunction) -> Arc<Value> {
         Self::new(name.to_string(), FuncBody::Native(native))
     }
 
     pub fn from_native_with_arity(
         name: &'static str,
-        native: fn(&[Arc<Value>]) -> Result<Arc<Value>, Error>,
+        native: NativeFunction,
         arity: usize,
     ) -> Arc<Value> {
         Self::new_with_arity(name.to_string(), FuncBody::Native(native), arity)
     }
 
-    pub fn call(&self, args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+    // Calls the function, leaving the body of a lambda for the caller to
+    // evaluate.
+    pub fn tail_call(&self, args: &[Arc<Value>]) -> Result<Trampoline, Error> {
         match &self.body {
             FuncBody::Native(function) => {
-                if let Some(arity) = self.arity {
-                    if args.len() != arity {
-                        return Err(Error::ArityError);
-                    }
-                }
-                function(args)
+                let result = match self.arity {
+                    Some(arity) => Arity::Exactly(arity).check(args.len()),
+                    None => Ok(()),
+                };
+                result
+                    .and_then(|_| function(args))
+                    .map(Trampoline::Return)
+                    .map_err(|err| err.with_operator(&self.name))
+            }
+            FuncBody::Lambda(lambda) => lambda.enter(&lambda.bind_args(&self.name, args)?),
+            FuncBody::Continuation(continuation) => {
+                Arity::Exactly(1)
+                    .check(args.len())
+                    .map_err(|err| err.with_operator(&self.name))?;
+                Err(continuation.escape(&args[0]))
             }
-            FuncBody::Lambda(lambda) => lambda.call(args),
         }
     }
 }
 
-pub type NativeSpecialForm = fn(&Arc<Scope>, &[Arc<Value>]) -> Result<Arc<Value>, Error>;
+// What eval should do after a special form or function call: either use the
+// result as is, or keep going by evaluating another value in its place.
+pub enum Trampoline {
+    Return(Arc<Value>),
+    TailCall(Arc<Scope>, Arc<Value>),
+}
+
+impl Trampoline {
+    pub fn resolve(self) -> Result<Arc<Value>, Error> {
+        match self {
+            Trampoline::Return(value) => Ok(value),
+            Trampoline::TailCall(scope, value) => eval(&scope, &value),
+        }
+    }
+}
+
+pub type NativeSpecialForm = fn(&Arc<Scope>, &[Arc<Value>]) -> Result<Trampoline, Error>;
 
 pub enum SpecialFormBody {
     Native(NativeSpecialForm),
     Macro(Macro),
+    SyntaxRules(SyntaxRules),
 }
 
 pub struct SpecialForm {
     pub name: String,
-    pub arity: usize,
+    pub arity: Option<Arity>,
     pub body: SpecialFormBody,
+    expansions: ExpansionCache,
+}
+
+// The expansions of the uses of a macro, keyed by the address of the form
+// being expanded, so that a use inside a loop is only expanded the first time
+// it is evaluated. The form is held weakly, and entries for forms that have
+// since been dropped are pruned as new ones are added. Pruning walks every
+// entry, so it is only done once the cache has doubled in size since it was
+// last pruned, which keeps the cost of each insert constant on average.
+#[derive(Default)]
+struct ExpansionCache {
+    entries: RwLock<HashMap<usize, CachedExpansion>>,
+    prune_at: AtomicUsize,
+}
+
+// The fewest entries the cache holds before it is pruned.
+const MIN_PRUNE_AT: usize = 16;
+
+type CachedExpansion = (Weak<Value>, Arc<Value>);
+
+impl ExpansionCache {
+    fn get(&self, form: &Arc<Value>) -> Option<Arc<Value>> {
+        let entries = self.entries.read().unwrap();
+        let (cached, code) = entries.get(&(Arc::as_ptr(form) as usize))?;
+        cached
+            .upgrade()
+            .filter(|cached| Arc::ptr_eq(cached, form))
+            .map(|_| code.clone())
+    }
+
+    fn insert(&self, form: &Arc<Value>, code: Arc<Value>) {
+        let mut entries = self.entries.write().unwrap();
+        if entries.len() >= self.prune_at.load(Ordering::Relaxed) {
+            entries.retain(|_, (cached, _)| cached.strong_count() > 0);
+            self.prune_at
+                .store((entries.len() * 2).max(MIN_PRUNE_AT), Ordering::Relaxed);
+        }
+        entries.insert(Arc::as_ptr(form) as usize, (Arc::downgrade(form), code));
+    }
 }
 
 impl std::fmt::Debug for SpecialForm {
@@ -251,72 +566,174 @@ impl std::fmt::Debug for SpecialForm {
 }
 
 impl std::cmp::PartialEq for SpecialForm {
-    fn eq(&self, _other: &Self) -> bool {
-        false
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
     }
 }
 
 impl std::cmp::Eq for SpecialForm {}
 
 impl SpecialForm {
-    pub fn new(name: String, arity: usize, body: NativeSpecialForm) -> Arc<Value> {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(name: String, arity: Option<Arity>, body: NativeSpecialForm) -> Arc<Value> {
+        Self::from_body(name, arity, SpecialFormBody::Native(body))
+    }
+
+    pub fn from_body(name: String, arity: Option<Arity>, body: SpecialFormBody) -> Arc<Value> {
         Arc::new(Value::SpecialForm(SpecialForm {
             name,
             arity,
-            body: SpecialFormBody::Native(body),
+            body,
+            expansions: ExpansionCache::default(),
         }))
     }
 
     pub fn from_native(name: &'static str, arity: usize, native: NativeSpecialForm) -> Arc<Value> {
-        Self::new(name.to_string(), arity, native)
+        Self::new(name.to_string(), Some(Arity::Exactly(arity)), native)
     }
 
-    pub fn call(&self, scope: &Arc<Scope>, args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
-        // TODO: Handle arity like for lambda.
-        if args.len() != self.arity {
-            return Err(Error::ArityError);
+    // Variadic special forms check their own arguments, and macros check
+    // theirs against their formals.
+    pub fn from_variadic_native(name: &'static str, native: NativeSpecialForm) -> Arc<Value> {
+        Self::new(name.to_string(), None, native)
+    }
+
+    // Calls the special form on the arguments of form, which is the whole
+    // (name args...) list. A macro's expansion is evaluated in scope.
+    pub fn call(
+        &self,
+        scope: &Arc<Scope>,
+        form: &Arc<Value>,
+        args: &[Arc<Value>],
+    ) -> Result<Trampoline, Error> {
+        if let Some(arity) = self.arity {
+            arity
+                .check(args.len())
+                .map_err(|err| err.with_operator(&self.name))?;
         }
         match &self.body {
             SpecialFormBody::Native(function) => function(scope, args),
-            SpecialFormBody::Macro(macro_) => macro_.call(scope, args),
+            _ => Ok(Trampoline::TailCall(
+                scope.clone(),
+                self.expand(form, args)?,
+            )),
         }
+        .map_err(|err| err.with_operator(&self.name))
+    }
+
+    pub fn is_macro(&self) -> bool {
+        !matches!(self.body, SpecialFormBody::Native(_))
+    }
+
+    // The code that form, a use of this macro with the given arguments,
+    // expands to. Expanding it again gives the same code without rerunning
+    // the macro.
+    pub fn expand(&self, form: &Arc<Value>, args: &[Arc<Value>]) -> Result<Arc<Value>, Error> {
+        if let Some(code) = self.expansions.get(form) {
+            return Ok(code);
+        }
+        let args: Vec<Arc<Value>> = args.iter().map(resolve::unresolve).collect();
+        let code = match &self.body {
+            SpecialFormBody::Native(_) => {
+                return Err(Error::eval_error(format!("Not a macro: {}", self.name)))
+            }
+            SpecialFormBody::Macro(macro_) => macro_.expand(&args),
+            SpecialFormBody::SyntaxRules(rules) => rules.expand(&args),
+        }
+        .map_err(|err| err.with_operator(&self.name))?;
+        self.expansions.insert(form, code.clone());
+        Ok(code)
     }
 }
 
-fn print_list(buffer: &mut String, cell: &Arc<Value>) {
+// A cell that is already being printed further up is part of a cycle, and is
+// printed as #cycle rather than printed again forever.
+#[allow(clippy::single_char_add_str)]
+fn print_list(buffer: &mut String, cell: &Arc<Value>, printing: &mut HashSet<*const Value>) {
     buffer.push_str("(");
-    let mut first = true;
-    let mut maybe_current = cell;
-    while let Value::Cell(cell) = maybe_current.deref() {
-        if first {
-            first = false;
-        } else {
-            buffer.push_str(" ");
+    let mut entered = Vec::new();
+    let mut maybe_current = cell.clone();
+    loop {
+        let key = Arc::as_ptr(&maybe_current);
+        match maybe_current.deref() {
+            Value::Cell(cell) if printing.insert(key) => {
+                if !entered.is_empty() {
+                    buffer.push_str(" ");
+                }
+                entered.push(key);
+                print_value(buffer, &cell.left(), printing);
+                maybe_current = cell.right();
+            }
+            Value::Nil => break,
+            _ => {
+                buffer.push_str(" . ");
+                print_value(buffer, &maybe_current, printing);
+                break;
+            }
         }
-        print_value(buffer, &cell.left);
-        maybe_current = &cell.right;
     }
-    match maybe_current.deref() {
-        Value::Nil => {}
-        _ => {
-            buffer.push_str(" . ");
-            print_value(buffer, maybe_current);
-        }
+    for key in entered {
+        printing.remove(&key);
     }
     buffer.push_str(")");
 }
 
-fn print_value(buffer: &mut String, value: &Arc<Value>) {
+// Prints a string literal that the parser reads back as the same string.
+fn print_string(buffer: &mut String, string: &str) {
+    buffer.push('"');
+    for ch in string.chars() {
+        match ch {
+            '"' => buffer.push_str("\\\""),
+            '\\' => buffer.push_str("\\\\"),
+            '\n' => buffer.push_str("\\n"),
+            '\t' => buffer.push_str("\\t"),
+            '\r' => buffer.push_str("\\r"),
+            '\0' => buffer.push_str("\\0"),
+            ch if ch.is_control() => buffer.push_str(&format!("\\u{{{:x}}}", ch as u32)),
+            ch => buffer.push(ch),
+        }
+    }
+    buffer.push('"');
+}
+
+fn print_value(buffer: &mut String, value: &Arc<Value>, printing: &mut HashSet<*const Value>) {
     match value.deref() {
+        Value::Cell(_) if printing.contains(&Arc::as_ptr(value)) => {
+            buffer.push_str("#cycle");
+        }
         Value::Cell(_) => {
-            print_list(buffer, value);
+            print_list(buffer, value, printing);
+        }
+        Value::Symbol(symbol) => {
+            buffer.push_str(&symbol.to_string());
         }
-        Value::Symbol(name) => {
-            buffer.push_str(name);
+        Value::Alias(alias) => {
+            buffer.push_str(&alias.original.to_string());
+        }
+        Value::Local(local) => {
+            buffer.push_str(&local.original.to_string());
+        }
+        Value::Number(number) => {
+            buffer.push_str(&number.to_string());
+        }
+        Value::String(string) => {
+            print_string(buffer, string);
         }
         Value::Quoted(value) => {
             buffer.push('\'');
-            print_value(buffer, value);
+            print_value(buffer, value, printing);
+        }
+        Value::Quasiquoted(value) => {
+            buffer.push('`');
+            print_value(buffer, value, printing);
+        }
+        Value::Unquoted(value) => {
+            buffer.push(',');
+            print_value(buffer, value, printing);
+        }
+        Value::UnquoteSpliced(value) => {
+            buffer.push_str(",@");
+            print_value(buffer, value, printing);
         }
         Value::Function(_) => {
             buffer.push_str("#func");
@@ -324,14 +741,27 @@ fn print_value(buffer: &mut String, value: &Arc<Value>) {
         Value::SpecialForm(_) => {
             buffer.push_str("#special_form");
         }
+        Value::Condition(condition) => {
+            buffer.push_str("#condition(");
+            buffer.push_str(condition.kind);
+            buffer.push(' ');
+            print_string(buffer, &condition.message);
+            buffer.push(')');
+        }
         Value::Nil => {
             buffer.push_str("nil");
         }
+        Value::Bool(value) => {
+            buffer.push_str(if *value { "#t" } else { "#f" });
+        }
+        Value::Unassigned => {
+            buffer.push_str("#unassigned");
+        }
     }
 }
 
 pub fn to_string(value: &Arc<Value>) -> String {
     let mut buffer = String::new();
-    print_value(&mut buffer, value);
+    print_value(&mut buffer, value, &mut HashSet::new());
     buffer
 }